@@ -0,0 +1,264 @@
+use bevy::asset::HandleId;
+use bevy::prelude::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::{Health, Velocity};
+use crate::damage::DamageEvent;
+use crate::feedback::HitStop;
+use crate::game_state::AppState;
+
+const WALK_SPEED: f32 = 1.0; // Anything slower than this is standing still as far as the animation is concerned.
+
+// Sprite animation in two layers.  SpriteAnimation plays a range of frames and knows when it's done.
+// Animator sits on top: it decides which named clip from the AnimationLibrary should be playing, based on what the entity is up to.
+// Effects and telegraphs just use a SpriteAnimation on its own.
+pub struct AnimationPlugin;
+
+impl Plugin for AnimationPlugin {
+	fn build(&self, app: &mut App) {
+		app.add_event::<AnimationFinished>();
+		app.insert_resource(AnimationLibrary::default());
+		app.add_system_set(
+			SystemSet::on_update(AppState::Playing)
+				.with_system(update_animators.label(AnimationSystem::Animators))
+				.with_system(play_sprite_animations.after(AnimationSystem::Animators))
+		);
+	}
+}
+
+#[derive(SystemLabel, Clone, Hash, Debug, PartialEq, Eq)]
+enum AnimationSystem {
+	Animators,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+pub enum AnimationState {
+	Idle,
+	Walk,
+	Cast,
+	Hurt,
+	Die,
+}
+
+impl AnimationState {
+	// A one-shot plays through unless something with a higher priority comes along.  Die never ends.
+	fn priority(&self) -> u8 {
+		match self {
+			AnimationState::Idle | AnimationState::Walk => 0,
+			AnimationState::Cast => 1,
+			AnimationState::Hurt => 2,
+			AnimationState::Die => 3,
+		}
+	}
+}
+
+// One named animation on a sheet.  Listed per sprite in the RON files under `animations`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct AnimationClip {
+	pub first: usize,
+	pub last: usize, // Inclusive.
+	pub fps: f32,
+	pub mode: PlaybackMode,
+}
+
+impl AnimationClip {
+	fn animation(&self) -> SpriteAnimation {
+		SpriteAnimation::new(self.first, self.last, 1.0 / self.fps.max(1e-3), self.mode)
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum PlaybackMode {
+	Loop, // first..=last, then back to first.
+	Once, // first..=last, then hold on last.
+	PingPong, // first..=last..=first, forever.
+}
+
+// Resources:
+// Clips by atlas.  Whoever loads a sheet registers its clips here; anything drawn with that atlas can then use them.
+#[derive(Default)]
+pub struct AnimationLibrary {
+	clips: HashMap<HandleId, HashMap<AnimationState, AnimationClip>>,
+}
+
+impl AnimationLibrary {
+	// Replaces whatever was there, so hot reloads just call this again.
+	pub fn register(&mut self, atlas: &Handle<TextureAtlas>, clips: HashMap<AnimationState, AnimationClip>) {
+		self.clips.insert(atlas.id, clips);
+	}
+
+	pub fn clip(&self, atlas: &Handle<TextureAtlas>, state: AnimationState) -> Option<&AnimationClip> {
+		self.clips.get(&atlas.id).and_then(|clips| clips.get(&state))
+	}
+}
+
+// Events:
+// Sent when a Once animation reaches its last frame.
+pub struct AnimationFinished {
+	pub entity: Entity,
+	pub state: Option<AnimationState>, // Which Animator clip it was.  None for a bare SpriteAnimation, like an effect.
+}
+
+// Components:
+// Picks clips for a sprite.  Walk/Idle come from Velocity, Hurt from damage, Die from Health.  Anything else gets triggered.
+#[derive(Component, Default)]
+pub struct Animator {
+	state: Option<AnimationState>, // None until the atlas has clips registered.
+	triggered: Option<AnimationState>, // Asked for since the last update.
+}
+
+impl Animator {
+	// For states gameplay has to tell us about, like Cast.  The most important request since the last update wins.
+	pub fn trigger(&mut self, state: AnimationState) {
+		if self.triggered.map_or(true, |triggered| state.priority() > triggered.priority()) {
+			self.triggered = Some(state);
+		}
+	}
+}
+
+#[derive(Component, Clone, Debug)]
+pub struct SpriteAnimation {
+	first: usize,
+	last: usize, // Inclusive.
+	mode: PlaybackMode,
+	frame_timer: Timer,
+	reversing: bool, // Ping-pong on the way back down.
+	finished: bool,
+	despawn_when_finished: bool,
+}
+
+impl SpriteAnimation {
+	pub fn new(first: usize, last: usize, frame_time: f32, mode: PlaybackMode) -> Self {
+		SpriteAnimation {
+			first,
+			last: last.max(first),
+			mode,
+			frame_timer: Timer::from_seconds(frame_time.max(1e-3), true),
+			reversing: false,
+			finished: false,
+			despawn_when_finished: false,
+		}
+	}
+
+	// For effects.  Only means anything with PlaybackMode::Once.
+	pub fn despawn_when_finished(mut self) -> Self {
+		self.despawn_when_finished = true;
+		self
+	}
+
+	pub fn first_frame(&self) -> usize {
+		self.first
+	}
+
+	pub fn finished(&self) -> bool {
+		self.finished
+	}
+
+	// Where to go from `index`, or None if a Once animation has nowhere left to go.
+	fn next_frame(&mut self, index: usize) -> Option<usize> {
+		// Something else moved the sprite outside our range.  Start over.
+		if index < self.first || index > self.last {
+			return Some(self.first);
+		}
+		match self.mode {
+			PlaybackMode::Loop => Some(if index >= self.last { self.first } else { index + 1 }),
+			PlaybackMode::Once => if index >= self.last { None } else { Some(index + 1) },
+			PlaybackMode::PingPong => {
+				if self.first == self.last {
+					return Some(self.first);
+				}
+				if self.reversing && index <= self.first {
+					self.reversing = false;
+				} else if !self.reversing && index >= self.last {
+					self.reversing = true;
+				}
+				Some(if self.reversing { index - 1 } else { index + 1 })
+			},
+		}
+	}
+}
+
+// Systems:
+fn update_animators(
+	mut commands: Commands,
+	library: Res<AnimationLibrary>,
+	mut damage_events: EventReader<DamageEvent>,
+	mut query: Query<(Entity, &mut Animator, &Handle<TextureAtlas>, &mut TextureAtlasSprite, Option<&mut SpriteAnimation>, Option<&Velocity>, Option<&Health>)>,
+) {
+	for event in damage_events.iter() {
+		if let Ok((_, mut animator, ..)) = query.get_mut(event.target) {
+			animator.trigger(AnimationState::Hurt);
+		}
+	}
+
+	for (entity, mut animator, atlas, mut sprite, animation, velocity, health) in query.iter_mut() {
+		let locomotion = match velocity {
+			Some(velocity) if velocity.0.truncate().length_squared() > WALK_SPEED * WALK_SPEED => AnimationState::Walk,
+			_ => AnimationState::Idle,
+		};
+		let mut wanted = if health.map_or(false, |health| health.0 <= 0.0) { AnimationState::Die } else { locomotion };
+		if let Some(triggered) = animator.triggered.take() {
+			if triggered.priority() > wanted.priority() {
+				wanted = triggered;
+			}
+		}
+
+		// Let a one-shot that's still going finish, unless this outranks it.
+		if let (Some(current), Some(animation)) = (animator.state, animation.as_ref()) {
+			let busy = current.priority() > 0 && (!animation.finished() || current == AnimationState::Die);
+			if busy && wanted.priority() <= current.priority() {
+				continue;
+			}
+		}
+
+		// Sheets don't have to have every clip.  Fall back to walking or standing around.
+		let (state, clip) = match [wanted, locomotion, AnimationState::Idle].iter().find_map(|state| library.clip(atlas, *state).map(|clip| (*state, clip))) {
+			Some(found) => found,
+			None => continue, // Not registered yet.
+		};
+		// Already on it.  A finished one-shot can play again though, say for a second hit.
+		if animator.state == Some(state) && animation.as_ref().map_or(false, |animation| state.priority() == 0 || !animation.finished()) {
+			continue;
+		}
+
+		animator.state = Some(state);
+		sprite.index = clip.first;
+		match animation {
+			Some(mut animation) => *animation = clip.animation(),
+			None => {
+				commands.entity(entity).insert(clip.animation());
+			},
+		}
+	}
+}
+
+fn play_sprite_animations(
+	mut commands: Commands,
+	time: Res<Time>,
+	hit_stop: Res<HitStop>,
+	mut finished_events: EventWriter<AnimationFinished>,
+	mut query: Query<(Entity, &mut SpriteAnimation, &mut TextureAtlasSprite, Option<&Animator>)>,
+) {
+	let delta = hit_stop.delta(&time);
+	for (entity, mut animation, mut sprite, animator) in query.iter_mut() {
+		if animation.finished {
+			continue;
+		}
+		animation.frame_timer.tick(delta);
+		// A long frame (or a short frame_time) can cover several frames at once.
+		for _ in 0..animation.frame_timer.times_finished() {
+			match animation.next_frame(sprite.index) {
+				Some(index) => sprite.index = index,
+				None => {
+					animation.finished = true;
+					finished_events.send(AnimationFinished { entity, state: animator.and_then(|animator| animator.state) });
+					if animation.despawn_when_finished {
+						commands.entity(entity).despawn();
+					}
+					break;
+				},
+			}
+		}
+	}
+}
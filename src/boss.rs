@@ -0,0 +1,322 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{Health, SpriteSheets, WindowBounds, ui_text};
+use crate::animation::{AnimationState, Animator};
+use crate::enemy::spawn_enemy_from_archetype;
+use crate::enemy_ai::{EnemyBehaviour, Steering};
+use crate::feedback::HitStop;
+use crate::game_state::AppState;
+use crate::enemy_archetype::{EnemyArchetypes, EnemyArchetypeSet};
+use crate::player::Player;
+use crate::projectile::{spawn_bullet, BulletPattern};
+
+const BOSS_SPAWN_OFFSET_FROM_TOP: f32 = 40.0;
+const SUMMON_RADIUS: f32 = 24.0;
+const BOSS_TEXT_COLOR: Color = Color::rgb(1.0, 0.4, 0.4);
+const HEALTH_BAR_COLOR: Color = Color::rgb(0.8, 0.1, 0.1);
+const HEALTH_BAR_BACKGROUND: Color = Color::rgba(0.0, 0.0, 0.0, 0.6);
+
+// A boss is a regular enemy (from an archetype) with a Boss component on top.
+// Phases kick in as health drops, each swapping movement and the attack rotation.
+pub struct BossPlugin;
+
+impl Plugin for BossPlugin {
+	fn build(&self, app: &mut App) {
+		app.insert_resource(BossWave::default());
+		app.add_system_set(
+			SystemSet::on_update(AppState::Playing)
+				.with_system(spawn_boss)
+				.with_system(update_boss_phases)
+				.with_system(boss_attacks)
+				.with_system(update_boss_health_bar)
+		);
+		app.add_system_set(SystemSet::on_exit(AppState::Playing).with_system(despawn_boss_health_bar));
+	}
+}
+
+// Lives in the wave script.  See waves.script.ron.
+#[derive(Clone, Debug, Deserialize)]
+pub struct BossSpec {
+	pub name: String,
+	pub archetype: String,
+	#[serde(default)]
+	pub health: Option<f32>, // Overrides the archetype's health.
+	pub phases: Vec<BossPhase>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct BossPhase {
+	pub health_fraction: f32, // Phase starts once health is at or below this fraction of max.  First phase should be 1.0.
+	pub behaviour: EnemyBehaviour,
+	pub speed: f32,
+	pub attack_interval: f32,
+	pub attacks: Vec<BossAttack>, // Used in order, looping.
+	#[serde(default)]
+	pub announcement: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub enum BossAttack {
+	Shoot { pattern: BulletPattern, speed: f32, damage: f32 },
+	Summon { archetype: String, count: u32 },
+}
+
+// Resources:
+// What the current wave wants in the way of a boss.  Set when the wave starts.
+#[derive(Default)]
+pub struct BossWave {
+	pub spec: Option<BossSpec>,
+	pub spawned: bool,
+}
+
+// Components:
+#[derive(Component)]
+pub struct Boss {
+	pub name: String,
+	pub max_health: f32,
+	pub phases: Vec<BossPhase>,
+	pub current_phase: usize,
+	pub attack_timer: Timer,
+	pub next_attack: usize,
+}
+
+#[derive(Component)]
+struct BossHealthBar;
+
+#[derive(Component)]
+struct BossHealthFill;
+
+// Systems:
+fn spawn_boss(
+	mut commands: Commands,
+	mut boss_wave: ResMut<BossWave>,
+	asset_server: Res<AssetServer>,
+	archetypes: Res<EnemyArchetypes>,
+	archetype_assets: Res<Assets<EnemyArchetypeSet>>,
+	window: Res<WindowBounds>,
+	mut ui_text_events: EventWriter<ui_text::UIText>,
+	player: Query<(&Transform, With<Player>)>,
+) {
+	if boss_wave.spawned {
+		return;
+	}
+	let spec = match boss_wave.spec.clone() {
+		Some(spec) => spec,
+		None => return,
+	};
+	let player_position = match player.iter().next() {
+		Some((tf, _)) => tf.translation.truncate(),
+		None => return,
+	};
+	let archetype_set = match archetype_assets.get(&archetypes.0) {
+		Some(set) => set,
+		None => return, // Still loading.
+	};
+	// Anything wrong with the spec won't fix itself, so complain once and make it a normal wave.
+	// Otherwise complete_wave sits waiting on a boss that never shows up.
+	let archetype = match archetype_set.get(&spec.archetype) {
+		Some(archetype) => archetype,
+		None => {
+			warn!("Boss '{}' uses unknown archetype '{}'.  Skipping the boss.", spec.name, spec.archetype);
+			boss_wave.spec = None;
+			return;
+		}
+	};
+	let first_phase = match spec.phases.first() {
+		Some(phase) => phase.clone(),
+		None => {
+			warn!("Boss '{}' has no phases.  Skipping the boss.", spec.name);
+			boss_wave.spec = None;
+			return;
+		}
+	};
+
+	let max_health = spec.health.unwrap_or(archetype.health);
+	let position = Vec2::new(0.0, window.top - BOSS_SPAWN_OFFSET_FROM_TOP);
+	let entity = spawn_enemy_from_archetype(&mut commands, archetype, position, player_position);
+	commands.entity(entity)
+		.insert(Health(max_health))
+		.insert(first_phase.behaviour)
+		.insert(Steering {
+			max_speed: first_phase.speed,
+			turn_rate: archetype.turn_rate,
+			..Steering::with_max_speed(first_phase.speed)
+		})
+		.insert(Boss {
+			name: spec.name.clone(),
+			max_health,
+			current_phase: 0,
+			attack_timer: Timer::from_seconds(first_phase.attack_interval, true),
+			next_attack: 0,
+			phases: spec.phases.clone(),
+		});
+
+	// Health bar across the top of the screen, with the name over it.
+	commands
+		.spawn_bundle(NodeBundle {
+			style: Style {
+				position_type: PositionType::Absolute,
+				position: Rect {
+					left: Val::Percent(20.0),
+					top: Val::Px(24.0),
+					..Default::default()
+				},
+				size: Size::new(Val::Percent(60.0), Val::Px(14.0)),
+				..Default::default()
+			},
+			color: UiColor(HEALTH_BAR_BACKGROUND),
+			..Default::default()
+		})
+		.insert(BossHealthBar)
+		.with_children(|parent| {
+			parent
+				.spawn_bundle(NodeBundle {
+					style: Style {
+						size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+						..Default::default()
+					},
+					color: UiColor(HEALTH_BAR_COLOR),
+					..Default::default()
+				})
+				.insert(BossHealthFill);
+			parent.spawn_bundle(TextBundle {
+				style: Style {
+					position_type: PositionType::Absolute,
+					position: Rect {
+						left: Val::Px(0.0),
+						bottom: Val::Px(16.0),
+						..Default::default()
+					},
+					..Default::default()
+				},
+				text: Text::with_section(
+					spec.name.clone(),
+					TextStyle {
+						font: asset_server.load("OpenSans-Regular.ttf"),
+						font_size: 24.0,
+						color: Color::WHITE,
+					},
+					Default::default()
+				),
+				..Default::default()
+			});
+		});
+
+	// Outranks the "Wave N" that went up at the same time.
+	ui_text_events.send(ui_text::UIText::from_string(spec.name.clone()).with_color(BOSS_TEXT_COLOR).with_priority(ui_text::TextPriority::High));
+	boss_wave.spawned = true;
+}
+
+fn update_boss_phases(
+	mut ui_text_events: EventWriter<ui_text::UIText>,
+	mut boss_query: Query<(&mut Boss, &Health, &mut EnemyBehaviour, &mut Steering), Changed<Health>>,
+) {
+	for (mut boss, health, mut behaviour, mut steering) in boss_query.iter_mut() {
+		let fraction = health.0 / boss.max_health.max(1e-6);
+		// Phases only ever move forward, even if something heals the boss.
+		let mut phase_index = boss.current_phase;
+		while phase_index + 1 < boss.phases.len() && fraction <= boss.phases[phase_index + 1].health_fraction {
+			phase_index += 1;
+		}
+		if phase_index == boss.current_phase {
+			continue;
+		}
+
+		let phase = boss.phases[phase_index].clone();
+		boss.current_phase = phase_index;
+		boss.next_attack = 0;
+		boss.attack_timer = Timer::from_seconds(phase.attack_interval, true);
+		*behaviour = phase.behaviour;
+		steering.max_speed = phase.speed;
+		if let Some(announcement) = phase.announcement {
+			ui_text_events.send(ui_text::UIText::from_string(announcement).with_size(60.0).with_anchor(ui_text::TextAnchor::Top).with_color(BOSS_TEXT_COLOR).with_timing(0.1, 2.5, 0.5));
+		}
+	}
+}
+
+fn boss_attacks(
+	mut commands: Commands,
+	time: Res<Time>,
+	hit_stop: Res<HitStop>,
+	sprite_sheets: Res<SpriteSheets>,
+	archetypes: Res<EnemyArchetypes>,
+	archetype_assets: Res<Assets<EnemyArchetypeSet>>,
+	player: Query<(&Transform, With<Player>)>,
+	mut boss_query: Query<(&Transform, &mut Boss, Option<&mut Animator>)>,
+) {
+	let player_position = player.iter().next().map(|(tf, _)| tf.translation.truncate());
+
+	for (transform, mut boss, animator) in boss_query.iter_mut() {
+		boss.attack_timer.tick(hit_stop.delta(&time));
+		if !boss.attack_timer.just_finished() {
+			continue;
+		}
+		let phase = &boss.phases[boss.current_phase];
+		if phase.attacks.is_empty() {
+			continue;
+		}
+		let attack = phase.attacks[boss.next_attack % phase.attacks.len()].clone();
+		boss.next_attack += 1;
+		if let Some(mut animator) = animator {
+			animator.trigger(AnimationState::Cast);
+		}
+
+		let origin = transform.translation.truncate();
+		let aim = player_position.map(|p| (p - origin).normalize_or_zero()).unwrap_or(-Vec2::Y);
+		match attack {
+			BossAttack::Shoot { pattern, speed, damage } => {
+				// The attack count doubles as the shot number so spirals keep turning between volleys.
+				for direction in pattern.directions(aim, boss.next_attack as u32) {
+					spawn_bullet(&mut commands, &sprite_sheets, origin, direction * speed, damage);
+				}
+			},
+			BossAttack::Summon { archetype, count } => {
+				let minion = match archetype_assets.get(&archetypes.0).and_then(|set| set.get(&archetype)) {
+					Some(minion) => minion,
+					None => {
+						warn!("Boss '{}' tried to summon unknown archetype '{}'.", boss.name, archetype);
+						continue;
+					}
+				};
+				for i in 0..count {
+					let angle = (i as f32 / count.max(1) as f32) * 2.0 * std::f32::consts::PI;
+					let position = origin + Vec2::new(angle.cos(), angle.sin()) * SUMMON_RADIUS;
+					spawn_enemy_from_archetype(&mut commands, minion, position, player_position.unwrap_or(origin));
+				}
+			},
+		}
+	}
+}
+
+fn update_boss_health_bar(
+	mut commands: Commands,
+	boss_query: Query<(&Boss, &Health)>,
+	changed_boss_query: Query<(&Boss, &Health), Changed<Health>>,
+	bar_query: Query<(Entity, With<BossHealthBar>)>,
+	mut fill_query: Query<&mut Style, With<BossHealthFill>>,
+) {
+	// Boss is dead (or never existed).  Take the bar down.
+	if boss_query.iter().next().is_none() {
+		for (entity, _) in bar_query.iter() {
+			commands.entity(entity).despawn_recursive();
+		}
+		return;
+	}
+
+	for (boss, health) in changed_boss_query.iter() {
+		let fraction = (health.0 / boss.max_health.max(1e-6)).clamp(0.0, 1.0);
+		for mut style in fill_query.iter_mut() {
+			style.size.width = Val::Percent(fraction * 100.0);
+		}
+	}
+}
+
+fn despawn_boss_health_bar(
+	mut commands: Commands,
+	bar_query: Query<(Entity, With<BossHealthBar>)>,
+) {
+	for (entity, _) in bar_query.iter() {
+		commands.entity(entity).despawn_recursive();
+	}
+}
@@ -0,0 +1,143 @@
+use bevy::prelude::*;
+
+use crate::GameplayCamera;
+use crate::feedback::FeedbackSettings;
+use crate::player::{Player, PlayerDamaged, PlayerDied};
+
+const TRAUMA_PER_DAMAGE: f32 = 0.35;
+const TRAUMA_ON_DEATH: f32 = 0.8;
+
+// Trauma-based camera shake.  Anything that wants the screen to shake sends a ShakeEvent with some trauma (0 to 1).
+// Trauma drains away over time, and the actual shake is trauma squared, so small knocks barely register and big ones really kick.
+// The shake is smooth noise on top of a base position, so the camera always settles back where it should be.
+pub struct CameraPlugin;
+
+impl Plugin for CameraPlugin {
+	fn build(&self, app: &mut App) {
+		app.add_event::<ShakeEvent>();
+		app.insert_resource(CameraSettings::default());
+		app.insert_resource(CameraShake::default());
+		app.add_system(shake_on_player_hurt.label(CameraSystem::Shakes));
+		app.add_system(add_trauma.label(CameraSystem::Trauma).after(CameraSystem::Shakes));
+		app.add_system(update_camera.after(CameraSystem::Trauma));
+	}
+}
+
+#[derive(SystemLabel, Clone, Hash, Debug, PartialEq, Eq)]
+enum CameraSystem {
+	Shakes,
+	Trauma,
+}
+
+// Events:
+pub struct ShakeEvent {
+	pub trauma: f32, // Added to the current trauma.  1.0 is as bad as it gets.
+}
+
+// Resources:
+pub struct CameraSettings {
+	pub max_offset: f32, // World units at full trauma.
+	pub max_angle: f32, // Radians at full trauma.
+	pub trauma_decay: f32, // Per second.
+	pub noise_frequency: f32, // How fast the shake wanders.  Higher is more rattly.
+	pub follow_player: f32, // 0 keeps the arena centred, 1 sticks to the player.  The arena is screen-sized, so keep it small.
+}
+
+impl Default for CameraSettings {
+	fn default() -> Self {
+		CameraSettings {
+			max_offset: 12.0,
+			max_angle: 0.05,
+			trauma_decay: 1.2,
+			noise_frequency: 25.0,
+			follow_player: 0.0,
+		}
+	}
+}
+
+#[derive(Default)]
+pub struct CameraShake {
+	pub trauma: f32,
+	noise_time: f32,
+}
+
+// Systems:
+fn shake_on_player_hurt(
+	mut shake_events: EventWriter<ShakeEvent>,
+	mut player_damaged_events: EventReader<PlayerDamaged>,
+	mut player_died_events: EventReader<PlayerDied>,
+) {
+	for event in player_damaged_events.iter() {
+		shake_events.send(ShakeEvent { trauma: event.amount * TRAUMA_PER_DAMAGE });
+	}
+	for _ in player_died_events.iter() {
+		shake_events.send(ShakeEvent { trauma: TRAUMA_ON_DEATH });
+	}
+}
+
+fn add_trauma(
+	mut shake: ResMut<CameraShake>,
+	mut shake_events: EventReader<ShakeEvent>,
+) {
+	for event in shake_events.iter() {
+		shake.trauma = (shake.trauma + event.trauma.max(0.0)).min(1.0);
+	}
+}
+
+fn update_camera(
+	time: Res<Time>,
+	settings: Res<CameraSettings>,
+	feedback_settings: Res<FeedbackSettings>,
+	mut shake: ResMut<CameraShake>,
+	player: Query<&Transform, (With<Player>, Without<GameplayCamera>)>,
+	mut camera_query: Query<&mut Transform, With<GameplayCamera>>,
+) {
+	let dt = time.delta_seconds();
+	let mut camera_transform = match camera_query.iter_mut().next() {
+		Some(transform) => transform,
+		None => return,
+	};
+
+	// Where the camera would be with no shake at all.  Depth stays put or the orthographic camera loses everything.
+	let base = player.iter().next().map(|tf| tf.translation.truncate() * settings.follow_player).unwrap_or(Vec2::ZERO);
+
+	let amount = shake.trauma * shake.trauma * feedback_settings.screen_shake_intensity.max(0.0);
+	let (offset, angle) = if amount > 0.0 {
+		shake.noise_time += dt * settings.noise_frequency;
+		let t = shake.noise_time;
+		(
+			Vec2::new(perlin_1d(t, 0), perlin_1d(t, 1)) * settings.max_offset * amount,
+			perlin_1d(t, 2) * settings.max_angle * amount,
+		)
+	} else {
+		(Vec2::ZERO, 0.0)
+	};
+
+	camera_transform.translation.x = base.x + offset.x;
+	camera_transform.translation.y = base.y + offset.y;
+	camera_transform.rotation = Quat::from_rotation_z(angle);
+
+	shake.trauma = (shake.trauma - settings.trauma_decay * dt).max(0.0);
+}
+
+// 1D gradient noise.  Smooth, roughly -1 to 1, and each seed gives an unrelated curve.
+fn perlin_1d(x: f32, seed: u32) -> f32 {
+	let cell = x.floor();
+	let t = x - cell;
+	let g0 = gradient(cell as i32, seed);
+	let g1 = gradient(cell as i32 + 1, seed);
+	let fade = t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+	let a = g0 * t;
+	let b = g1 * (t - 1.0);
+	// A single octave peaks at 0.5, so scale it up to use the whole range.
+	(a + (b - a) * fade) * 2.0
+}
+
+// Hashes a lattice point to a slope between -1 and 1.
+fn gradient(i: i32, seed: u32) -> f32 {
+	let mut h = (i as u32).wrapping_mul(0x27d4_eb2d) ^ seed.wrapping_mul(0x9e37_79b9);
+	h ^= h >> 15;
+	h = h.wrapping_mul(0x85eb_ca6b);
+	h ^= h >> 13;
+	(h & 0xffff) as f32 / 32767.5 - 1.0
+}
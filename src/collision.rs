@@ -0,0 +1,386 @@
+use bevy::core::FixedTimestep;
+use bevy::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+const COLLISION_TIMESTEP: f64 = 1.0 / 60.0;
+const SPATIAL_HASH_CELL_SIZE: f32 = 32.0; // About two sprites wide.  Should be bigger than most colliders.
+
+// Collision layers.  An entity sits on one (or more) layers and has a mask of the layers it cares about.
+pub const LAYER_PLAYER: u32 = 1 << 0;
+pub const LAYER_ENEMY: u32 = 1 << 1;
+pub const LAYER_PLAYER_PROJECTILE: u32 = 1 << 2;
+pub const LAYER_ENEMY_PROJECTILE: u32 = 1 << 3;
+pub const LAYER_PICKUP: u32 = 1 << 4;
+
+// Every fixed tick we drop all colliders into a uniform grid, test only the things that share a cell,
+// and send a CollisionEvent for each overlapping pair where at least one side's mask matches the other's layer.
+pub struct CollisionPlugin;
+
+impl Plugin for CollisionPlugin {
+	fn build(&self, app: &mut App) {
+		app.insert_resource(SpatialHash::new(SPATIAL_HASH_CELL_SIZE));
+		app.add_event::<CollisionEvent>();
+		app.add_system_set(
+			SystemSet::new()
+				.with_run_criteria(FixedTimestep::step(COLLISION_TIMESTEP))
+				.with_system(update_collisions)
+		);
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColliderShape {
+	Circle(f32), // Radius.
+	Aabb(Vec2), // Half extents.
+	SpriteRect, // Use the current frame's rect from the entity's texture atlas.
+}
+
+// Components:
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Collider {
+	pub shape: ColliderShape,
+	pub layer: u32,
+	pub mask: u32,
+}
+
+impl Collider {
+	pub fn new(shape: ColliderShape, layer: u32, mask: u32) -> Self {
+		Collider {
+			shape,
+			layer,
+			mask,
+		}
+	}
+}
+
+// Events:
+#[derive(Clone, Copy, Debug)]
+pub struct CollisionEvent {
+	pub a: Entity,
+	pub b: Entity,
+	pub a_layer: u32,
+	pub b_layer: u32,
+}
+
+impl CollisionEvent {
+	// If this collision is between something on `first` and something on `second`, hand them back in that order.
+	pub fn between(&self, first: u32, second: u32) -> Option<(Entity, Entity)> {
+		if self.a_layer & first != 0 && self.b_layer & second != 0 {
+			Some((self.a, self.b))
+		} else if self.b_layer & first != 0 && self.a_layer & second != 0 {
+			Some((self.b, self.a))
+		} else {
+			None
+		}
+	}
+}
+
+// Resources:
+#[derive(Clone, Copy, Debug)]
+enum ResolvedShape {
+	Circle(f32),
+	Aabb(Vec2),
+}
+
+impl ResolvedShape {
+	fn half_extents(&self) -> Vec2 {
+		match *self {
+			ResolvedShape::Circle(radius) => Vec2::new(radius, radius),
+			ResolvedShape::Aabb(half_extents) => half_extents,
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug)]
+struct HashEntry {
+	entity: Entity,
+	position: Vec2,
+	shape: ResolvedShape,
+	layer: u32,
+	mask: u32,
+}
+
+pub struct SpatialHash {
+	cell_size: f32,
+	cells: HashMap<(i32, i32), Vec<usize>>,
+	entries: Vec<HashEntry>,
+}
+
+impl SpatialHash {
+	pub fn new(cell_size: f32) -> Self {
+		SpatialHash {
+			cell_size,
+			cells: HashMap::new(),
+			entries: Vec::new(),
+		}
+	}
+
+	pub fn clear(&mut self) {
+		// Keep the allocations for cells used last tick, since things don't move far between ticks.
+		// Cells that sat empty for a whole tick go, so stuff flying off into the distance can't grow the map forever.
+		self.cells.retain(|_, cell| {
+			let used = !cell.is_empty();
+			cell.clear();
+			used
+		});
+		self.entries.clear();
+	}
+
+	fn cell_range(&self, position: Vec2, half_extents: Vec2) -> ((i32, i32), (i32, i32)) {
+		let min = position - half_extents;
+		let max = position + half_extents;
+		(
+			((min.x / self.cell_size).floor() as i32, (min.y / self.cell_size).floor() as i32),
+			((max.x / self.cell_size).floor() as i32, (max.y / self.cell_size).floor() as i32),
+		)
+	}
+
+	fn insert(&mut self, entry: HashEntry) {
+		let index = self.entries.len();
+		let ((min_x, min_y), (max_x, max_y)) = self.cell_range(entry.position, entry.shape.half_extents());
+		self.entries.push(entry);
+		for y in min_y..=max_y {
+			for x in min_x..=max_x {
+				self.cells.entry((x, y)).or_insert_with(Vec::new).push(index);
+			}
+		}
+	}
+
+	// Everything on one of `layers` that overlaps the given circle.
+	pub fn query_circle(&self, center: Vec2, radius: f32, layers: u32) -> Vec<Entity> {
+		let probe = ResolvedShape::Circle(radius);
+		let ((min_x, min_y), (max_x, max_y)) = self.cell_range(center, probe.half_extents());
+		let mut seen = HashSet::new();
+		let mut result = Vec::new();
+		for y in min_y..=max_y {
+			for x in min_x..=max_x {
+				if let Some(cell) = self.cells.get(&(x, y)) {
+					for &index in cell.iter() {
+						let entry = &self.entries[index];
+						if entry.layer & layers != 0 && seen.insert(index) && overlaps(center, probe, entry.position, entry.shape) {
+							result.push(entry.entity);
+						}
+					}
+				}
+			}
+		}
+		result
+	}
+
+	// Each interested, overlapping pair once.
+	fn overlapping_pairs(&self) -> Vec<(usize, usize)> {
+		let mut tested = HashSet::new();
+		let mut pairs = Vec::new();
+		for cell in self.cells.values() {
+			for (i, &a_index) in cell.iter().enumerate() {
+				for &b_index in cell[i + 1..].iter() {
+					let key = (a_index.min(b_index), a_index.max(b_index));
+					if !tested.insert(key) {
+						continue; // Big things sit in several cells.  Only test each pair once.
+					}
+					let a = &self.entries[key.0];
+					let b = &self.entries[key.1];
+					let interested = (a.mask & b.layer) != 0 || (b.mask & a.layer) != 0;
+					if interested && overlaps(a.position, a.shape, b.position, b.shape) {
+						pairs.push(key);
+					}
+				}
+			}
+		}
+		pairs
+	}
+}
+
+fn overlaps(a_position: Vec2, a_shape: ResolvedShape, b_position: Vec2, b_shape: ResolvedShape) -> bool {
+	match (a_shape, b_shape) {
+		(ResolvedShape::Circle(a_radius), ResolvedShape::Circle(b_radius)) => {
+			a_position.distance_squared(b_position) <= (a_radius + b_radius) * (a_radius + b_radius)
+		},
+		(ResolvedShape::Aabb(a_half), ResolvedShape::Aabb(b_half)) => {
+			let delta = (a_position - b_position).abs();
+			delta.x <= a_half.x + b_half.x && delta.y <= a_half.y + b_half.y
+		},
+		(ResolvedShape::Circle(radius), ResolvedShape::Aabb(half)) => circle_overlaps_aabb(a_position, radius, b_position, half),
+		(ResolvedShape::Aabb(half), ResolvedShape::Circle(radius)) => circle_overlaps_aabb(b_position, radius, a_position, half),
+	}
+}
+
+fn circle_overlaps_aabb(center: Vec2, radius: f32, box_center: Vec2, half_extents: Vec2) -> bool {
+	let closest = center.clamp(box_center - half_extents, box_center + half_extents);
+	center.distance_squared(closest) <= radius * radius
+}
+
+// Systems:
+fn update_collisions(
+	mut spatial_hash: ResMut<SpatialHash>,
+	mut collision_events: EventWriter<CollisionEvent>,
+	atlases: Res<Assets<TextureAtlas>>,
+	query: Query<(Entity, &Transform, &Collider, Option<&TextureAtlasSprite>, Option<&Handle<TextureAtlas>>)>,
+) {
+	spatial_hash.clear();
+
+	for (entity, transform, collider, sprite, atlas_handle) in query.iter() {
+		let scale = Vec2::new(transform.scale.x.abs(), transform.scale.y.abs());
+		let shape = match collider.shape {
+			ColliderShape::Circle(radius) => ResolvedShape::Circle(radius * scale.max_element()),
+			ColliderShape::Aabb(half_extents) => ResolvedShape::Aabb(half_extents * scale),
+			ColliderShape::SpriteRect => {
+				let rect = match (sprite, atlas_handle.and_then(|h| atlases.get(h))) {
+					(Some(sprite), Some(atlas)) => atlas.textures.get(sprite.index).cloned(),
+					_ => None,
+				};
+				match rect {
+					Some(rect) => ResolvedShape::Aabb((rect.max - rect.min) * 0.5 * scale),
+					None => continue, // Atlas isn't loaded yet.  Can't hit what we can't see.
+				}
+			},
+		};
+
+		spatial_hash.insert(HashEntry {
+			entity,
+			position: transform.translation.truncate(),
+			shape,
+			layer: collider.layer,
+			mask: collider.mask,
+		});
+	}
+
+	for (a_index, b_index) in spatial_hash.overlapping_pairs() {
+		let a = &spatial_hash.entries[a_index];
+		let b = &spatial_hash.entries[b_index];
+		collision_events.send(CollisionEvent {
+			a: a.entity,
+			b: b.entity,
+			a_layer: a.layer,
+			b_layer: b.layer,
+		});
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use rand::{Rng, thread_rng};
+	use std::time::Instant;
+
+	fn entry(id: u32, position: Vec2, shape: ResolvedShape, layer: u32, mask: u32) -> HashEntry {
+		HashEntry {
+			entity: Entity::from_raw(id),
+			position,
+			shape,
+			layer,
+			mask,
+		}
+	}
+
+	#[test]
+	fn circles_overlap_up_to_touching() {
+		let circle = ResolvedShape::Circle(5.0);
+		assert!(overlaps(Vec2::ZERO, circle, Vec2::new(10.0, 0.0), circle));
+		assert!(!overlaps(Vec2::ZERO, circle, Vec2::new(10.1, 0.0), circle));
+		assert!(overlaps(Vec2::ZERO, circle, Vec2::new(6.0, 6.0), circle));
+		assert!(!overlaps(Vec2::ZERO, circle, Vec2::new(7.1, 7.1), circle));
+	}
+
+	#[test]
+	fn boxes_overlap_on_both_axes_only() {
+		let a = ResolvedShape::Aabb(Vec2::new(4.0, 2.0));
+		let b = ResolvedShape::Aabb(Vec2::new(1.0, 1.0));
+		assert!(overlaps(Vec2::ZERO, a, Vec2::new(5.0, 3.0), b));
+		assert!(!overlaps(Vec2::ZERO, a, Vec2::new(5.5, 0.0), b));
+		assert!(!overlaps(Vec2::ZERO, a, Vec2::new(0.0, 3.5), b));
+	}
+
+	#[test]
+	fn circle_against_box() {
+		let half = Vec2::new(4.0, 4.0);
+		// Straight out from a side.
+		assert!(circle_overlaps_aabb(Vec2::new(6.0, 0.0), 2.0, Vec2::ZERO, half));
+		assert!(!circle_overlaps_aabb(Vec2::new(6.5, 0.0), 2.0, Vec2::ZERO, half));
+		// Off a corner, where the bounding boxes overlap but the shapes don't.
+		assert!(!circle_overlaps_aabb(Vec2::new(5.6, 5.6), 2.0, Vec2::ZERO, half));
+		assert!(circle_overlaps_aabb(Vec2::new(5.4, 5.4), 2.0, Vec2::ZERO, half));
+		// Circle entirely inside the box.
+		assert!(circle_overlaps_aabb(Vec2::new(1.0, 1.0), 0.5, Vec2::ZERO, half));
+		// Mixed pairs come out the same whichever way round they're passed.
+		let circle = ResolvedShape::Circle(2.0);
+		let aabb = ResolvedShape::Aabb(half);
+		assert!(overlaps(Vec2::new(6.0, 0.0), circle, Vec2::ZERO, aabb));
+		assert!(overlaps(Vec2::ZERO, aabb, Vec2::new(6.0, 0.0), circle));
+		assert!(!overlaps(Vec2::ZERO, aabb, Vec2::new(5.6, 5.6), circle));
+	}
+
+	#[test]
+	fn between_orders_by_layer() {
+		let spell = Entity::from_raw(1);
+		let enemy = Entity::from_raw(2);
+		let event = CollisionEvent { a: enemy, b: spell, a_layer: LAYER_ENEMY, b_layer: LAYER_PLAYER_PROJECTILE };
+		assert_eq!(event.between(LAYER_PLAYER_PROJECTILE, LAYER_ENEMY), Some((spell, enemy)));
+		assert_eq!(event.between(LAYER_ENEMY, LAYER_PLAYER_PROJECTILE), Some((enemy, spell)));
+		assert_eq!(event.between(LAYER_PLAYER, LAYER_ENEMY), None);
+		assert_eq!(event.between(LAYER_ENEMY_PROJECTILE, LAYER_PLAYER), None);
+	}
+
+	#[test]
+	fn big_pair_across_many_cells_is_reported_once() {
+		let mut hash = SpatialHash::new(8.0);
+		// Both cover a 5x5 block of cells, all of them shared.
+		hash.insert(entry(0, Vec2::new(20.0, 20.0), ResolvedShape::Aabb(Vec2::new(18.0, 18.0)), LAYER_ENEMY, LAYER_PLAYER));
+		hash.insert(entry(1, Vec2::new(21.0, 21.0), ResolvedShape::Circle(18.0), LAYER_PLAYER, LAYER_ENEMY));
+		// Uninterested in each other, so never a pair even though they overlap everything.
+		hash.insert(entry(2, Vec2::new(20.0, 20.0), ResolvedShape::Circle(4.0), LAYER_PICKUP, 0));
+		assert_eq!(hash.overlapping_pairs(), vec![(0, 1)]);
+	}
+
+	#[test]
+	fn query_circle_filters_by_layer_and_shape() {
+		let mut hash = SpatialHash::new(8.0);
+		hash.insert(entry(0, Vec2::new(0.0, 0.0), ResolvedShape::Circle(20.0), LAYER_ENEMY, 0));
+		hash.insert(entry(1, Vec2::new(5.0, 0.0), ResolvedShape::Circle(1.0), LAYER_ENEMY, 0));
+		hash.insert(entry(2, Vec2::new(5.0, 0.0), ResolvedShape::Circle(1.0), LAYER_PLAYER, 0));
+		hash.insert(entry(3, Vec2::new(40.0, 0.0), ResolvedShape::Circle(1.0), LAYER_ENEMY, 0));
+		let mut found = hash.query_circle(Vec2::new(4.0, 0.0), 3.0, LAYER_ENEMY);
+		found.sort_by_key(|entity| entity.id());
+		assert_eq!(found, vec![Entity::from_raw(0), Entity::from_raw(1)]);
+	}
+
+	#[test]
+	fn clear_drops_cells_that_went_unused() {
+		let mut hash = SpatialHash::new(8.0);
+		hash.insert(entry(0, Vec2::new(1000.0, 1000.0), ResolvedShape::Circle(1.0), LAYER_ENEMY, 0));
+		hash.clear();
+		// Used last tick, so it's kept for reuse.
+		assert_eq!(hash.cells.len(), 1);
+		hash.insert(entry(0, Vec2::new(0.0, 0.0), ResolvedShape::Circle(1.0), LAYER_ENEMY, 0));
+		hash.clear();
+		hash.clear();
+		assert!(hash.cells.is_empty());
+		assert!(hash.entries.is_empty());
+	}
+
+	// Not a real benchmark, just a sanity check that a crowded arena stays well inside a frame.
+	// cargo test --release -- --ignored --nocapture spatial_hash_timing
+	#[test]
+	#[ignore]
+	fn spatial_hash_timing() {
+		const ENTITIES: u32 = 5000;
+		const TICKS: u32 = 100;
+		let mut rng = thread_rng();
+		let mut positions: Vec<Vec2> = (0..ENTITIES).map(|_| Vec2::new(rng.gen_range(-640.0, 640.0), rng.gen_range(-360.0, 360.0))).collect();
+		let mut hash = SpatialHash::new(SPATIAL_HASH_CELL_SIZE);
+		let mut pairs = 0;
+		let start = Instant::now();
+		for _ in 0..TICKS {
+			hash.clear();
+			for (i, position) in positions.iter_mut().enumerate() {
+				*position += Vec2::new(rng.gen_range(-2.0, 2.0), rng.gen_range(-2.0, 2.0));
+				let (layer, mask) = if i % 4 == 0 { (LAYER_PLAYER_PROJECTILE, LAYER_ENEMY) } else { (LAYER_ENEMY, LAYER_PLAYER) };
+				hash.insert(entry(i as u32, *position, ResolvedShape::Circle(6.0), layer, mask));
+			}
+			pairs += hash.overlapping_pairs().len();
+		}
+		let per_tick = start.elapsed() / TICKS;
+		println!("{} entities: {:?} per tick, {} pairs per tick, {} cells", ENTITIES, per_tick, pairs / TICKS as usize, hash.cells.len());
+		assert!(per_tick.as_secs_f64() < COLLISION_TIMESTEP);
+	}
+}
@@ -0,0 +1,104 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::Health;
+
+// Anything that wants to hurt something sends a DamageEvent.  This is the only place Health gets subtracted,
+// so status effects, damage numbers, and stats can all listen in one spot.
+pub struct DamagePlugin;
+
+impl Plugin for DamagePlugin {
+	fn build(&self, app: &mut App) {
+		app.add_event::<DamageEvent>();
+		app.add_system(apply_damage_events.label(DamageSystem));
+	}
+}
+
+// Anything that needs Health to already include this frame's hits runs after this.
+#[derive(SystemLabel, Clone, Hash, Debug, PartialEq, Eq)]
+pub struct DamageSystem;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+pub enum Element {
+	Physical,
+	Fire,
+	Ice,
+	Lightning,
+	Arcane,
+}
+
+impl Default for Element {
+	fn default() -> Self {
+		Element::Physical
+	}
+}
+
+impl Element {
+	// Damage numbers, impact sparks, anything that wants to show what kind of hit it was.
+	pub fn color(&self) -> Color {
+		match self {
+			Element::Physical => Color::rgb(0.9, 0.9, 0.9),
+			Element::Fire => Color::rgb(1.0, 0.5, 0.1),
+			Element::Ice => Color::rgb(0.5, 0.85, 1.0),
+			Element::Lightning => Color::rgb(1.0, 0.95, 0.3),
+			Element::Arcane => Color::rgb(0.8, 0.5, 1.0),
+		}
+	}
+}
+
+// Events:
+#[derive(Clone, Debug)]
+pub struct DamageEvent {
+	pub source: Entity,
+	pub target: Entity,
+	pub element: Element,
+	pub amount: f32, // After resistances.
+	pub critical: bool,
+	pub position: Vec2, // Where it landed.  Hit effects go here, since the target may be gone by the time they run.
+}
+
+// Components:
+// Damage dealt to whatever this entity bumps into.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct ContactDamage(pub f32);
+
+// Goes away after dealing its contact damage once.  Bullets, mostly.
+#[derive(Component)]
+pub struct DespawnOnContact;
+
+// Per-element damage multipliers.  Anything not listed takes normal damage.
+// 0.5 = resists half, 2.0 = weak to it, 0.0 = immune.  Negative values heal, so don't do that unless you mean it.
+#[derive(Component, Clone, Debug, Default, Deserialize)]
+pub struct Resistances(pub HashMap<Element, f32>);
+
+impl Resistances {
+	pub fn with(mut self, element: Element, multiplier: f32) -> Self {
+		self.0.insert(element, multiplier);
+		self
+	}
+
+	pub fn multiplier(&self, element: Element) -> f32 {
+		*self.0.get(&element).unwrap_or(&1.0)
+	}
+}
+
+pub fn compute_damage(base_damage: f32, element: Element, resistances: Option<&Resistances>) -> f32 {
+	match resistances {
+		Some(r) => base_damage * r.multiplier(element),
+		None => base_damage,
+	}
+}
+
+// Systems:
+fn apply_damage_events(
+	mut damage_events: EventReader<DamageEvent>,
+	mut health_query: Query<&mut Health>,
+) {
+	for event in damage_events.iter() {
+		// The target may have already been despawned by something else this frame.
+		if let Ok(mut health) = health_query.get_mut(event.target) {
+			health.0 -= event.amount;
+		}
+	}
+}
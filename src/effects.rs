@@ -0,0 +1,70 @@
+use bevy::prelude::*;
+
+use crate::{SpriteSheets, ENEMY_RENDER_PRIORITY};
+use crate::animation::{PlaybackMode, SpriteAnimation};
+
+// One-shot visual effects off the explosion sheet.  They play once and clean themselves up.
+const EFFECT_RENDER_PRIORITY: f32 = ENEMY_RENDER_PRIORITY + 0.1; // Over whatever just blew up.
+const EXPLOSION_FRAMES: usize = 6;
+const EXPLOSION_FRAME_TIME: f32 = 0.1;
+const DEATH_FRAME_TIME: f32 = 0.07;
+const IMPACT_FRAMES: usize = 3; // Just the flash at the start of the explosion.
+const IMPACT_FRAME_TIME: f32 = 0.03;
+const IMPACT_SCALE: f32 = 0.5;
+
+// Components:
+// Leftovers get swept up when a run ends.
+#[derive(Component)]
+pub struct Effect;
+
+// Spell explosions.  The sprite is 16x16, so a radius of 8 is unscaled.  Don't shrink it below that or it's invisible.
+pub fn spawn_explosion(
+	commands: &mut Commands,
+	sprite_sheets: &SpriteSheets,
+	position: Vec3,
+	radius: f32,
+) {
+	let scale = (radius / 8.0).max(1.0);
+	spawn_effect(commands, sprite_sheets, position, scale, SpriteAnimation::new(0, EXPLOSION_FRAMES - 1, EXPLOSION_FRAME_TIME, PlaybackMode::Once));
+}
+
+// Something died.  `scale` is whatever the dead thing was drawn at, so bosses go out bigger.
+pub fn spawn_death_effect(
+	commands: &mut Commands,
+	sprite_sheets: &SpriteSheets,
+	position: Vec2,
+	scale: f32,
+) {
+	spawn_effect(commands, sprite_sheets, position.extend(EFFECT_RENDER_PRIORITY), scale, SpriteAnimation::new(0, EXPLOSION_FRAMES - 1, DEATH_FRAME_TIME, PlaybackMode::Once));
+}
+
+// A projectile landed.
+pub fn spawn_impact_effect(
+	commands: &mut Commands,
+	sprite_sheets: &SpriteSheets,
+	position: Vec2,
+) {
+	spawn_effect(commands, sprite_sheets, position.extend(EFFECT_RENDER_PRIORITY), IMPACT_SCALE, SpriteAnimation::new(0, IMPACT_FRAMES - 1, IMPACT_FRAME_TIME, PlaybackMode::Once));
+}
+
+fn spawn_effect(
+	commands: &mut Commands,
+	sprite_sheets: &SpriteSheets,
+	position: Vec3,
+	scale: f32,
+	animation: SpriteAnimation,
+) {
+	commands
+		.spawn_bundle(SpriteSheetBundle {
+			texture_atlas: sprite_sheets.explosion.clone(),
+			sprite: TextureAtlasSprite::new(animation.first_frame()),
+			transform: Transform {
+				translation: position,
+				scale: Vec3::new(scale, scale, 1.0),
+				..Default::default()
+			},
+			..Default::default()
+		})
+		.insert(animation.despawn_when_finished())
+		.insert(Effect);
+}
@@ -2,138 +2,531 @@ use bevy::prelude::*;
 use rand::{Rng, thread_rng};
 use std::time::{Duration, Instant};
 use bevy::core::FixedTimestep;
-use bevy::sprite::collide_aabb::collide;
 
-use crate::{Health, SpriteSheets, Velocity, WindowBounds, ENEMY_RENDER_PRIORITY, ui_text};
+use crate::animation::{AnimationFinished, AnimationLibrary, AnimationState, Animator, PlaybackMode, SpriteAnimation};
+use crate::{Health, SpriteSheets, Velocity, WindowBounds, BACKGROUND_RENDER_PRIORITY, ENEMY_RENDER_PRIORITY, ui_text};
+use crate::boss::{Boss, BossWave};
+use crate::camera::ShakeEvent;
+use crate::enemy_archetype::{EnemyArchetype, EnemyArchetypeLoader, EnemyArchetypes, EnemyArchetypeSet, EnemySplit, ENEMY_ARCHETYPES_PATH};
+use crate::collision::{Collider, ColliderShape, CollisionEvent, SpatialHash, LAYER_ENEMY, LAYER_PLAYER, LAYER_PLAYER_PROJECTILE};
+use crate::damage::{compute_damage, ContactDamage, DamageEvent, Resistances};
+use crate::effects::{spawn_death_effect, spawn_explosion, spawn_impact_effect};
+use crate::feedback::HitStop;
+use crate::game_state::{AppState, LoadingAssets};
+use crate::enemy_ai::{EnemyMoveTarget, FleeAtLowHealth, Steering};
 use crate::player::Player;
-use crate::spells::SpellEffect;
+use crate::projectile::{Faction, ProjectileEmitter};
+use crate::spells::{OnHitEffect, Projectile, SpellEffect};
+use crate::waves::{SpawnPattern, SpawnRequest, WaveRunner, WaveScript, WaveScriptHandle, WaveScriptLoader, WAVE_SCRIPT_PATH};
 
-const ENEMY_SPEED: f32 = 6.0f32;
-const ENEMY_HEALTH: f32 = 1.0f32;
+const SPLIT_SCATTER: f32 = 6.0f32; // How far apart split children appear.
+const TELEGRAPH_COLOR: Color = Color::rgba(1.0, 0.2, 0.2, 0.6);
+const MAX_DYING_SECONDS: f32 = 3.0; // Backstop for a Die clip that never finishes, say one set to Loop.
 
 // Public Access:
 pub struct EnemyPlugin;
 
 impl Plugin for EnemyPlugin {
 	fn build(&self, app: &mut App) {
+		app.add_asset::<EnemyArchetypeSet>();
+		app.init_asset_loader::<EnemyArchetypeLoader>();
+		app.add_asset::<WaveScript>();
+		app.init_asset_loader::<WaveScriptLoader>();
+		app.insert_resource(SpawnSettings::default());
+		app.add_event::<EnemyKilled>();
+		app.add_event::<WaveCleared>();
 		app.add_startup_system(setup_enemy);
+		app.add_system(register_enemy_animations);
+		app.add_system_set(SystemSet::on_enter(AppState::Playing).with_system(reset_waves));
 		app.add_system_set(
-			SystemSet::new()
-				.with_run_criteria(FixedTimestep::step(1.0))
+			SystemSet::on_update(AppState::Playing)
 				.with_system(spawn_enemy)
+				.with_system(resolve_spawn_telegraphs)
+				.with_system(apply_spell_effects)
+				.with_system(count_and_remove_dead_enemies)
+				.with_system(remove_finished_corpses)
 		);
+		// Can't stack a state run criteria on a fixed timestep, so complete_wave checks the state itself.
 		app.add_system_set(
 			SystemSet::new()
 				.with_run_criteria(FixedTimestep::step(3.0))
 				.with_system(complete_wave)
 		);
-		app.add_system(apply_spell_effects);
-		app.add_system(count_and_remove_dead_enemies);
+		app.add_system_set(SystemSet::on_exit(AppState::Playing).with_system(despawn_enemies));
 	}
 }
 
 // Resources:
-struct Wave(u32);
+pub struct Wave(pub u32);
+
+pub struct ActiveEnemiesInWave(pub u32); // We make this a separate trait so we can lock it independently.
+
+pub struct PendingEnemiesInWave(pub u32);
+
+pub struct SpawnSettings {
+	pub min_player_distance: f32, // Nothing spawns closer than this to the player.
+	pub telegraph_seconds: f32, // Default warning time before an enemy appears.  Wave groups can override it.
+}
+
+impl Default for SpawnSettings {
+	fn default() -> Self {
+		SpawnSettings {
+			min_player_distance: 64.0,
+			telegraph_seconds: 0.75,
+		}
+	}
+}
 
-struct ActiveEnemiesInWave(u32); // We make this a separate trait so we can lock it independently.
+// Events:
+pub struct EnemyKilled {
+	pub position: Vec2,
+	pub score: u32, // The archetype's base score, before any multipliers.
+}
 
-struct PendingEnemiesInWave(u32);
+pub struct WaveCleared {
+	pub wave: u32,
+}
 
 // Components:
 #[derive(Component)]
-struct Enemy;
+pub struct Enemy;
+
+#[derive(Component)]
+pub struct ScoreValue(pub u32);
+
+#[derive(Component)]
+struct SplitsInto(EnemySplit);
 
+// A warning marker.  When the timer runs out it turns into the real (collidable) enemy.
 #[derive(Component)]
-struct EnemyMoveTarget(Vec2);
+pub struct SpawnTelegraph {
+	timer: Timer,
+	archetype: String,
+}
+
+// Dead, but still playing its Die clip.  No collider and no AI, so it's just a picture until it goes.
+#[derive(Component)]
+struct Dying(Timer);
 
 // Systems:
 fn setup_enemy(
 	mut commands: Commands,
+	asset_server: Res<AssetServer>,
+	mut loading: ResMut<LoadingAssets>,
 ) {
+	let archetypes: Handle<EnemyArchetypeSet> = asset_server.load(ENEMY_ARCHETYPES_PATH);
+	let script: Handle<WaveScript> = asset_server.load(WAVE_SCRIPT_PATH);
+	loading.0.push(archetypes.clone_untyped());
+	loading.0.push(script.clone_untyped());
+	commands.insert_resource(EnemyArchetypes(archetypes));
+	commands.insert_resource(WaveScriptHandle(script));
+	commands.insert_resource(WaveRunner::default());
 	commands.insert_resource(Wave(0));
 	commands.insert_resource(ActiveEnemiesInWave(0));
-	commands.insert_resource(PendingEnemiesInWave(1));
+	commands.insert_resource(PendingEnemiesInWave(0));
+}
+
+// Every run starts back at wave 0.  complete_wave picks it up from there.
+fn reset_waves(
+	mut wave: ResMut<Wave>,
+	mut runner: ResMut<WaveRunner>,
+	mut active_enemies: ResMut<ActiveEnemiesInWave>,
+	mut pending_enemies: ResMut<PendingEnemiesInWave>,
+	mut boss_wave: ResMut<BossWave>,
+) {
+	*runner = WaveRunner::default();
+	wave.0 = 0;
+	active_enemies.0 = 0;
+	pending_enemies.0 = 0;
+	*boss_wave = BossWave::default();
+}
+
+fn despawn_enemies(
+	mut commands: Commands,
+	enemies: Query<(Entity, With<Enemy>)>,
+	telegraphs: Query<(Entity, With<SpawnTelegraph>)>,
+) {
+	for (entity, _) in enemies.iter() {
+		commands.entity(entity).despawn();
+	}
+	for (entity, _) in telegraphs.iter() {
+		commands.entity(entity).despawn();
+	}
 }
 
 fn spawn_enemy(
 	mut commands: Commands,
+	time: Res<Time>,
+	hit_stop: Res<HitStop>,
+	mut runner: ResMut<WaveRunner>,
 	mut pending_enemies: ResMut<PendingEnemiesInWave>,
 	mut active_enemies: ResMut<ActiveEnemiesInWave>,
+	wave: Res<Wave>,
+	archetypes: Res<EnemyArchetypes>,
+	archetype_assets: Res<Assets<EnemyArchetypeSet>>,
+	settings: Res<SpawnSettings>,
 	sprite_sheets: Res<SpriteSheets>,
-	atlas_assets: Res<Assets<TextureAtlas>>,
 	player: Query<(&Transform, With<Player>)>, // So we know where to go.
 	window: Res<WindowBounds>,
 ) {
 	// Let's not spawn enemies until the player exists...
-	if player.iter().next().is_none() {
-		return;
-	}
-
-	if pending_enemies.0 > 0 {
-		let mut rng = thread_rng();
-		//let x = rng.gen::<f32>() * 10f32;
-		//let y = rng.gen::<f32>() * 10f32;
-		let x = rng.gen_range::<f32>(window.left, window.right);
-		let y = rng.gen_range(window.bottom, window.top);
+	let player_position = match player.iter().next() {
+		Some((player_transform, _)) => player_transform.translation.truncate(),
+		None => return,
+	};
+	// ...or before we know what enemies there are.
+	let archetype_set = match archetype_assets.get(&archetypes.0) {
+		Some(set) => set,
+		None => return,
+	};
 
-		// Set trajectory to player.
-		let (player_transform, _) = player.single();
-		let trajectory = Vec3::new(player_transform.translation.x - x, player_transform.translation.y - y, 0f32).normalize()*ENEMY_SPEED;
+	let mut rng = thread_rng();
+	for request in runner.tick(hit_stop.delta_seconds(&time)) {
+		let archetype = match request.archetype.as_ref() {
+			Some(name) => archetype_set.get(name).or_else(|| {
+				warn!("Wave script asks for unknown enemy archetype '{}'.", name);
+				archetype_set.pick_for_wave(wave.0)
+			}),
+			None => archetype_set.pick_for_wave(wave.0),
+		};
+		let archetype = match archetype {
+			Some(archetype) => archetype,
+			None => {
+				warn!("No enemy archetypes are allowed on wave {}.", wave.0);
+				continue;
+			}
+		};
 
-		commands
-			.spawn_bundle(SpriteSheetBundle {
-				texture_atlas: atlas_assets.get_handle(&sprite_sheets.enemy_material),
-				//transform: Transform::from_scale(Vec3::splat(6.0)),
-				transform: Transform {
-					translation: Vec3::new(x, y, ENEMY_RENDER_PRIORITY),
+		let position = resolve_spawn_position(&request, player_position, &window, &mut rng);
+		let position = keep_away_from_player(position, player_position, settings.min_player_distance, &window);
+		let telegraph_seconds = request.telegraph.unwrap_or(settings.telegraph_seconds);
+		if telegraph_seconds > 0.0 {
+			commands
+				.spawn_bundle(SpriteSheetBundle {
+					texture_atlas: sprite_sheets.explosion.clone(),
+					sprite: TextureAtlasSprite {
+						color: TELEGRAPH_COLOR,
+						..Default::default()
+					},
+					transform: Transform {
+						translation: position.extend(BACKGROUND_RENDER_PRIORITY + 0.5), // Under the actors, over the floor.
+						..Default::default()
+					},
 					..Default::default()
-				},
-				..Default::default()
-			})
-			.insert(Timer::from_seconds(0.1, true))
-			.insert(Health(ENEMY_HEALTH))
-			.insert(Velocity(trajectory))
-			.insert(Enemy);
-		pending_enemies.0 -= 1;
+				})
+				.insert(SpriteAnimation::new(0, 5, telegraph_seconds / 6.0, PlaybackMode::Once)) // Play the 6 frames once over the warning.
+				.insert(SpawnTelegraph {
+					timer: Timer::from_seconds(telegraph_seconds, false),
+					archetype: archetype.name.clone(),
+				});
+		} else {
+			spawn_enemy_from_archetype(&mut commands, archetype, position, player_position);
+		}
 		active_enemies.0 += 1;
 	}
+	// Only write on change.  The HUD redraws whenever this is touched.
+	let pending = runner.pending();
+	if pending_enemies.0 != pending {
+		pending_enemies.0 = pending;
+	}
+}
+
+fn resolve_spawn_telegraphs(
+	mut commands: Commands,
+	time: Res<Time>,
+	hit_stop: Res<HitStop>,
+	archetypes: Res<EnemyArchetypes>,
+	archetype_assets: Res<Assets<EnemyArchetypeSet>>,
+	player: Query<(&Transform, With<Player>)>,
+	mut telegraphs: Query<(Entity, &Transform, &mut SpawnTelegraph)>,
+) {
+	let archetype_set = archetype_assets.get(&archetypes.0);
+
+	for (entity, transform, mut telegraph) in telegraphs.iter_mut() {
+		telegraph.timer.tick(hit_stop.delta(&time));
+		if !telegraph.timer.finished() {
+			continue;
+		}
+
+		commands.entity(entity).despawn();
+		let position = transform.translation.truncate();
+		// If the player died in the meantime, just face wherever we are.  The AI will sort it out.
+		let target = player.iter().next().map(|(tf, _)| tf.translation.truncate()).unwrap_or(position);
+		match archetype_set.and_then(|set| set.get(&telegraph.archetype)) {
+			Some(archetype) => {
+				spawn_enemy_from_archetype(&mut commands, archetype, position, target);
+			},
+			None => warn!("Telegraphed enemy archetype '{}' went missing.", telegraph.archetype),
+		}
+	}
+}
+
+// Push a spawn point out to at least `min_distance` from the player, flipping to the other side if the window edge gets in the way.
+fn keep_away_from_player(
+	position: Vec2,
+	player_position: Vec2,
+	min_distance: f32,
+	window: &WindowBounds,
+) -> Vec2 {
+	let clamp_to_window = |p: Vec2| Vec2::new(p.x.clamp(window.left, window.right), p.y.clamp(window.bottom, window.top));
+	if position.distance(player_position) >= min_distance {
+		return position;
+	}
+
+	let mut direction = (position - player_position).normalize_or_zero();
+	if direction == Vec2::ZERO {
+		direction = Vec2::X; // Right on top of the player.  Any direction will do.
+	}
+	let pushed = clamp_to_window(player_position + direction * min_distance);
+	if pushed.distance(player_position) >= min_distance {
+		return pushed;
+	}
+	let flipped = clamp_to_window(player_position - direction * min_distance);
+	if flipped.distance(player_position) >= min_distance {
+		flipped
+	} else {
+		// Cornered in a window smaller than the safe radius.  Take whichever is further.
+		if flipped.distance(player_position) > pushed.distance(player_position) { flipped } else { pushed }
+	}
+}
+
+fn resolve_spawn_position<R: Rng>(
+	request: &SpawnRequest,
+	player_position: Vec2,
+	window: &WindowBounds,
+	rng: &mut R,
+) -> Vec2 {
+	let position = match &request.pattern {
+		SpawnPattern::Random => {
+			//let x = rng.gen::<f32>() * 10f32;
+			//let y = rng.gen::<f32>() * 10f32;
+			Vec2::new(rng.gen_range(window.left, window.right), rng.gen_range(window.bottom, window.top))
+		},
+		SpawnPattern::ScreenEdge => {
+			let t = rng.next_f32();
+			match rng.gen_range(0, 4) {
+				0 => Vec2::new(window.left, window.bottom + t * window.height),
+				1 => Vec2::new(window.right, window.bottom + t * window.height),
+				2 => Vec2::new(window.left + t * window.width, window.bottom),
+				_ => Vec2::new(window.left + t * window.width, window.top),
+			}
+		},
+		SpawnPattern::RingAroundPlayer { radius } => {
+			let angle = rng.next_f32() * 2.0 * std::f32::consts::PI;
+			player_position + Vec2::new(angle.cos(), angle.sin()) * *radius
+		},
+		SpawnPattern::Points(points) => {
+			match points.get(request.index as usize % points.len().max(1)) {
+				Some((x, y)) => Vec2::new(*x, *y),
+				None => player_position, // Empty list in the script.  Not much else we can do.
+			}
+		},
+		SpawnPattern::Burst { radius } => {
+			let angle = (request.index as f32 / request.count.max(1) as f32) * 2.0 * std::f32::consts::PI;
+			player_position + Vec2::new(angle.cos(), angle.sin()) * *radius
+		},
+	};
+
+	// Rings around a player near the edge would put things off screen.
+	Vec2::new(position.x.clamp(window.left, window.right), position.y.clamp(window.bottom, window.top))
+}
+
+// Archetypes load (and hot reload) as one asset.  Whenever it changes, hand every sheet's clips to the animation library.
+fn register_enemy_animations(
+	mut asset_events: EventReader<AssetEvent<EnemyArchetypeSet>>,
+	archetype_assets: Res<Assets<EnemyArchetypeSet>>,
+	mut library: ResMut<AnimationLibrary>,
+) {
+	for event in asset_events.iter() {
+		let handle = match event {
+			AssetEvent::Created { handle } | AssetEvent::Modified { handle } => handle,
+			AssetEvent::Removed { .. } => continue,
+		};
+		if let Some(set) = archetype_assets.get(handle) {
+			for archetype in set.archetypes.iter() {
+				library.register(&archetype.atlas, archetype.sprite.clips());
+			}
+		}
+	}
+}
+
+pub fn spawn_enemy_from_archetype(
+	commands: &mut Commands,
+	archetype: &EnemyArchetype,
+	position: Vec2,
+	target: Vec2,
+) -> Entity {
+	// Start off heading at the target.  Steering takes over from here.
+	let trajectory = (target - position).normalize_or_zero().extend(0.0) * archetype.speed;
+
+	let mut entity = commands.spawn_bundle(SpriteSheetBundle {
+		texture_atlas: archetype.atlas.clone(),
+		//transform: Transform::from_scale(Vec3::splat(6.0)),
+		transform: Transform {
+			translation: position.extend(ENEMY_RENDER_PRIORITY),
+			scale: Vec3::new(archetype.sprite.scale, archetype.sprite.scale, 1.0),
+			..Default::default()
+		},
+		..Default::default()
+	});
+	entity
+		.insert(Animator::default())
+		.insert(Health(archetype.health))
+		.insert(archetype.resistances.clone())
+		.insert(ContactDamage(archetype.contact_damage))
+		.insert(ScoreValue(archetype.score))
+		.insert(Collider::new(ColliderShape::SpriteRect, LAYER_ENEMY, LAYER_PLAYER | LAYER_PLAYER_PROJECTILE))
+		.insert(Velocity(trajectory))
+		.insert(EnemyMoveTarget(target))
+		.insert(archetype.behaviour)
+		.insert(Steering {
+			max_speed: archetype.speed,
+			turn_rate: archetype.turn_rate,
+			..Steering::with_max_speed(archetype.speed)
+		})
+		.insert(Enemy);
+	if let Some(threshold) = archetype.flee_below_health {
+		entity.insert(FleeAtLowHealth(threshold));
+	}
+	if let Some(split) = archetype.splits_into.as_ref() {
+		entity.insert(SplitsInto(split.clone()));
+	}
+	if let Some(emitter) = archetype.emitter.as_ref() {
+		entity.insert(ProjectileEmitter::new(emitter.clone()));
+	}
+	entity.id()
 }
 
 fn complete_wave(
 	mut commands: Commands,
+	state: Res<State<AppState>>,
 	mut wave: ResMut<Wave>,
+	mut runner: ResMut<WaveRunner>,
 	mut pending_enemies: ResMut<PendingEnemiesInWave>,
 	active_enemies: Res<ActiveEnemiesInWave>,
+	mut boss_wave: ResMut<BossWave>,
+	script_handle: Res<WaveScriptHandle>,
+	scripts: Res<Assets<WaveScript>>,
+	mut wave_cleared_events: EventWriter<WaveCleared>,
+	mut ui_text_events: EventWriter<ui_text::UIText>,
+	bosses: Query<With<Boss>>,
+	leftovers: Query<(Entity, With<Enemy>), Without<Dying>>, // Corpses finish their Die clip.  remove_finished_corpses has them.
 ) {
-	// This does nothing but bump our wave count and reset the number of enemies.
-	if pending_enemies.0 == 0 && active_enemies.0 == 0 {
+	// This does nothing but bump our wave count and queue up the next wave's spawns.
+	if *state.current() != AppState::Playing {
+		return;
+	}
+	let wave_done = if boss_wave.spec.is_some() {
+		// Boss waves end when the boss does.  Whatever it summoned goes with it.
+		let boss_dead = boss_wave.spawned && bosses.iter().next().is_none();
+		if boss_dead && pending_enemies.0 == 0 {
+			for (entity, _) in leftovers.iter() {
+				commands.entity(entity).despawn();
+			}
+		}
+		boss_dead && pending_enemies.0 == 0
+	} else {
+		pending_enemies.0 == 0 && active_enemies.0 == 0
+	};
+
+	if wave_done {
+		let script = match scripts.get(&script_handle.0) {
+			Some(script) => script,
+			None => return, // Still loading.
+		};
+		if wave.0 > 0 {
+			wave_cleared_events.send(WaveCleared { wave: wave.0 });
+		}
 		wave.0 += 1;
-		pending_enemies.0 = (1+wave.0)*2;
-		commands.spawn().insert(ui_text::UIText::from_string(format!("Wave {}", wave.0)));
+		let spec = script.wave(wave.0);
+		*boss_wave = BossWave {
+			spec: spec.boss.clone(),
+			spawned: false,
+		};
+		runner.start(spec);
+		pending_enemies.0 = runner.pending();
+		ui_text_events.send(ui_text::UIText::from_string(format!("Wave {}", wave.0)).with_priority(ui_text::TextPriority::Low));
 	}
 }
 
 fn apply_spell_effects(
-	mut enemy_query: Query<(&Transform, &mut Health, With<Enemy>)>,
-	spell_query: Query<(&Transform, &SpellEffect)>,
+	mut commands: Commands,
+	mut collision_events: EventReader<CollisionEvent>,
+	mut damage_events: EventWriter<DamageEvent>,
+	mut shake_events: EventWriter<ShakeEvent>,
+	sprite_sheets: Res<SpriteSheets>,
+	spatial_hash: Res<SpatialHash>,
+	enemy_query: Query<(&Transform, Option<&Resistances>), With<Enemy>>,
+	mut spell_query: Query<(&Transform, &SpellEffect, &mut Projectile, &Faction)>,
 ) {
-	// We should consider adding 'sprite' to this fray so we can compare the sizes.
-	for (enemy_transform, mut health, _) in enemy_query.iter_mut() {
-		for (spell_transform, spell_effect) in spell_query.iter() {
-			let hack_size = Vec2::new(8.0, 8.0);  // TODO: We should be better about how we me measure this distance.
-			//let enemy_size = Vec2::new(enemy_transform.scale.x, enemy_transform.scale.y);
-			//let spell_size = Vec2::new(spell_transform.scale.x, spell_transform.scale.y);
-			let collision = collide(
-				enemy_transform.translation,
-				hack_size,
-				spell_transform.translation,
-				hack_size
-			);
-			if let Some(_) = collision {
-				// TODO: We should match the type of the collision to the enemy resistance even before we do this.
-				health.0 -= spell_effect.base_damage;
+	let mut rng = thread_rng();
+	for collision in collision_events.iter() {
+		let (spell_entity, enemy_entity) = match collision.between(LAYER_PLAYER_PROJECTILE, LAYER_ENEMY) {
+			Some(pair) => pair,
+			None => continue,
+		};
+		// Either side may already be gone.  Collisions are found on the fixed tick, not every frame.
+		let (spell_transform, spell_effect, mut projectile, faction) = match spell_query.get_mut(spell_entity) {
+			Ok(spell) => spell,
+			Err(_) => continue,
+		};
+		if *faction != Faction::Player {
+			continue; // Only our own spells hurt enemies.
+		}
+		let (_, resistances) = match enemy_query.get(enemy_entity) {
+			Ok(enemy) => enemy,
+			Err(_) => continue,
+		};
+
+		// Each projectile damages a given enemy exactly once, no matter how many ticks they overlap.
+		// A spent projectile might still get events before its despawn goes through, so ignore those too.
+		if projectile.spent || projectile.hit_entities.contains(&enemy_entity) {
+			continue;
+		}
+
+		projectile.hit_entities.push(enemy_entity);
+		let critical = spell_effect.crit_chance > 0.0 && rng.next_f32() < spell_effect.crit_chance;
+		let base_damage = if critical { spell_effect.base_damage * spell_effect.crit_multiplier } else { spell_effect.base_damage };
+		damage_events.send(DamageEvent {
+			source: spell_entity,
+			target: enemy_entity,
+			element: spell_effect.element,
+			amount: compute_damage(base_damage, spell_effect.element, resistances),
+			critical,
+			position: spell_transform.translation.truncate(),
+		});
+		spawn_impact_effect(&mut commands, &sprite_sheets, spell_transform.translation.truncate());
+
+		for effect in projectile.on_hit.iter() {
+			match effect {
+				OnHitEffect::Explode { radius, damage } => {
+					spawn_explosion(&mut commands, &sprite_sheets, spell_transform.translation, *radius);
+					for other_entity in spatial_hash.query_circle(spell_transform.translation.truncate(), *radius, LAYER_ENEMY) {
+						if let Ok((other_transform, other_resistances)) = enemy_query.get(other_entity) {
+							damage_events.send(DamageEvent {
+								source: spell_entity,
+								target: other_entity,
+								element: spell_effect.element,
+								amount: compute_damage(*damage, spell_effect.element, other_resistances),
+								critical: false,
+								position: other_transform.translation.truncate(),
+							});
+						}
+					}
+				},
+				OnHitEffect::ScreenShake(trauma) => {
+					shake_events.send(ShakeEvent { trauma: *trauma });
+				},
 			}
 		}
+
+		// Out of pierce?  Then this projectile is spent.
+		if projectile.pierce_remaining == 0 {
+			projectile.spent = true;
+			commands.entity(spell_entity).despawn();
+		} else {
+			projectile.pierce_remaining -= 1;
+		}
 	}
 }
 
@@ -142,17 +535,168 @@ fn apply_spell_effects(
 fn count_and_remove_dead_enemies(
 	mut commands: Commands,
 	mut active_enemes: ResMut<ActiveEnemiesInWave>,
-	query: Query<(Entity, &Health, With<Enemy>)>,
+	mut killed_events: EventWriter<EnemyKilled>,
+	sprite_sheets: Res<SpriteSheets>,
+	library: Res<AnimationLibrary>,
+	archetypes: Res<EnemyArchetypes>,
+	archetype_assets: Res<Assets<EnemyArchetypeSet>>,
+	query: Query<(Entity, &Health, &Transform, &EnemyMoveTarget, &ScoreValue, Option<&SplitsInto>, &Handle<TextureAtlas>), (With<Enemy>, Without<Dying>)>,
+	telegraphs: Query<With<SpawnTelegraph>>,
 ) {
-	let mut live_enemies = 0; // Safer to count rather than rely on decrementing.
+	// Safer to count rather than rely on decrementing.  Enemies still being telegraphed count as alive.  The dying don't.
+	let mut live_enemies = telegraphs.iter().count() as u32;
+	let archetype_set = archetype_assets.get(&archetypes.0);
 
-	for (entity, health, _) in query.iter() {
-		if health.0 <= 0.0 {
-			commands.entity(entity).despawn();
-		} else {
+	for (entity, health, transform, move_target, score, splits_into, atlas) in query.iter() {
+		if health.0 > 0.0 {
 			live_enemies += 1;
+			continue;
+		}
+
+		// With a Die clip the Animator plays it out and remove_finished_corpses cleans up.  Without one, just blow up.
+		if library.clip(atlas, AnimationState::Die).is_some() {
+			commands.entity(entity)
+				.remove::<Collider>()
+				.remove::<Steering>()
+				.remove::<ProjectileEmitter>()
+				.remove::<Boss>() // Takes the health bar down and lets the boss wave end.
+				.insert(Velocity(Vec3::ZERO))
+				.insert(Dying(Timer::from_seconds(MAX_DYING_SECONDS, false)));
+		} else {
+			commands.entity(entity).despawn();
+			spawn_death_effect(&mut commands, &sprite_sheets, transform.translation.truncate(), transform.scale.x);
+		}
+		killed_events.send(EnemyKilled {
+			position: transform.translation.truncate(),
+			score: score.0,
+		});
+
+		// Splitters leave their children behind.  Count them now so the wave can't end before they show up.
+		if let (Some(SplitsInto(split)), Some(set)) = (splits_into, archetype_set) {
+			match set.get(&split.archetype) {
+				Some(child) => {
+					let origin = transform.translation.truncate();
+					for i in 0..split.count {
+						let angle = (i as f32 / split.count as f32) * 2.0 * std::f32::consts::PI;
+						let offset = Vec2::new(angle.cos(), angle.sin()) * SPLIT_SCATTER;
+						spawn_enemy_from_archetype(&mut commands, child, origin + offset, move_target.0);
+						live_enemies += 1;
+					}
+				},
+				None => warn!("Enemy splits into unknown archetype '{}'.", split.archetype),
+			}
 		}
 	}
 
-	active_enemes.0 = live_enemies;
+	if active_enemes.0 != live_enemies {
+		active_enemes.0 = live_enemies;
+	}
+}
+
+fn remove_finished_corpses(
+	mut commands: Commands,
+	time: Res<Time>,
+	hit_stop: Res<HitStop>,
+	mut finished_events: EventReader<AnimationFinished>,
+	mut dying: Query<(Entity, &mut Dying)>,
+) {
+	// Only the Die clip counts.  A Hurt or Cast that finished just as it died doesn't.
+	let mut finished: Vec<Entity> = finished_events.iter()
+		.filter(|event| event.state == Some(AnimationState::Die) && dying.get(event.entity).is_ok())
+		.map(|event| event.entity)
+		.collect();
+	for (entity, mut corpse) in dying.iter_mut() {
+		if corpse.0.tick(hit_stop.delta(&time)).just_finished() && !finished.contains(&entity) {
+			finished.push(entity);
+		}
+	}
+	for entity in finished {
+		commands.entity(entity).despawn();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::damage::{DamagePlugin, Element};
+
+	fn spell_app() -> App {
+		let mut app = App::new();
+		app.add_plugin(DamagePlugin);
+		app.add_event::<CollisionEvent>();
+		app.add_event::<ShakeEvent>();
+		app.insert_resource(SpriteSheets {
+			level_tileset: Handle::default(),
+			player_material: Handle::default(),
+			explosion: Handle::default(),
+		});
+		app.insert_resource(SpatialHash::new(32.0));
+		app.add_system(apply_spell_effects);
+		app
+	}
+
+	fn spawn_enemy_at(app: &mut App, x: f32) -> Entity {
+		app.world.spawn()
+			.insert(Enemy)
+			.insert(Health(10.0))
+			.insert(Transform::from_xyz(x, 0.0, 0.0))
+			.id()
+	}
+
+	fn collide(app: &mut App, spell: Entity, enemy: Entity) {
+		app.world.get_resource_mut::<Events<CollisionEvent>>().unwrap().send(CollisionEvent {
+			a: spell,
+			b: enemy,
+			a_layer: LAYER_PLAYER_PROJECTILE,
+			b_layer: LAYER_ENEMY,
+		});
+	}
+
+	#[test]
+	fn each_target_is_hit_once_and_pierce_runs_out() {
+		let mut app = spell_app();
+		let first = spawn_enemy_at(&mut app, 0.0);
+		let second = spawn_enemy_at(&mut app, 10.0);
+		let third = spawn_enemy_at(&mut app, 20.0);
+		let spell = app.world.spawn()
+			.insert(Transform::default())
+			.insert(SpellEffect { base_damage: 3.0, element: Element::Fire, crit_chance: 0.0, crit_multiplier: 2.0 })
+			.insert(Projectile { pierce_remaining: 1, hit_entities: Vec::new(), on_hit: Vec::new(), spent: false })
+			.insert(Faction::Player)
+			.id();
+
+		let mut reader = app.world.get_resource::<Events<DamageEvent>>().unwrap().get_reader();
+		let mut hits = Vec::new();
+		let mut update = |app: &mut App, hits: &mut Vec<Entity>| {
+			app.update();
+			let events = app.world.get_resource::<Events<DamageEvent>>().unwrap();
+			hits.extend(reader.iter(events).map(|event| event.target));
+		};
+
+		// Overlapping the first enemy for a few collision ticks, sometimes twice in one frame.
+		collide(&mut app, spell, first);
+		collide(&mut app, spell, first);
+		update(&mut app, &mut hits);
+		collide(&mut app, spell, first);
+		update(&mut app, &mut hits);
+		assert_eq!(hits, vec![first]);
+		assert!(app.world.get_entity(spell).is_some()); // Still has one pierce left.
+
+		// The second hit uses up the pierce.  The third enemy in the same frame gets nothing.
+		collide(&mut app, spell, first);
+		collide(&mut app, spell, second);
+		collide(&mut app, spell, third);
+		update(&mut app, &mut hits);
+		assert_eq!(hits, vec![first, second]);
+		assert!(app.world.get_entity(spell).is_none());
+
+		// Late events for a projectile that's already gone.
+		collide(&mut app, spell, third);
+		update(&mut app, &mut hits);
+		assert_eq!(hits, vec![first, second]);
+
+		assert_eq!(app.world.get::<Health>(first).unwrap().0, 7.0);
+		assert_eq!(app.world.get::<Health>(second).unwrap().0, 7.0);
+		assert_eq!(app.world.get::<Health>(third).unwrap().0, 10.0);
+	}
 }
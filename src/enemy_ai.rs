@@ -0,0 +1,165 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{Health, Velocity};
+use crate::collision::{SpatialHash, LAYER_ENEMY};
+use crate::enemy::Enemy;
+use crate::feedback::HitStop;
+use crate::game_state::AppState;
+use crate::player::Player;
+
+const ARRIVE_DISTANCE: f32 = 4.0; // Start slowing down when this close to the move target so we don't jitter on top of it.
+const ORBIT_LEAD_ANGLE: f32 = 0.5; // Radians ahead on the circle we aim for while orbiting.
+const FLEE_DISTANCE: f32 = 100.0;
+
+// Behaviours pick a move target from the live player position, then steering turns toward it at a limited rate.
+pub struct EnemyAiPlugin;
+
+impl Plugin for EnemyAiPlugin {
+	fn build(&self, app: &mut App) {
+		app.add_system_set(
+			SystemSet::on_update(AppState::Playing)
+				.with_system(update_move_targets.label(EnemyAiSystem::Target))
+				.with_system(steer_enemies.label(EnemyAiSystem::Steer).after(EnemyAiSystem::Target))
+		);
+	}
+}
+
+#[derive(SystemLabel, Clone, Hash, Debug, PartialEq, Eq)]
+enum EnemyAiSystem {
+	Target,
+	Steer,
+}
+
+// Components:
+#[derive(Component)]
+pub struct EnemyMoveTarget(pub Vec2);
+
+#[derive(Component, Clone, Copy, Debug, Deserialize)]
+pub enum EnemyBehaviour {
+	Chase,
+	Orbit { radius: f32 },
+	KeepDistance { distance: f32 }, // For ranged types.  Backs off if the player gets too close.
+}
+
+// Overrides the behaviour and runs away once health drops to this or lower.
+#[derive(Component, Clone, Copy, Debug, Deserialize)]
+pub struct FleeAtLowHealth(pub f32);
+
+#[derive(Component, Clone, Copy, Debug, Deserialize)]
+pub struct Steering {
+	pub max_speed: f32,
+	pub turn_rate: f32, // Radians per second.
+	pub separation_radius: f32, // Other enemies closer than this push us away.
+	pub separation_strength: f32, // 0 = ignore the crowd.  1 = as strong as chasing at full speed.
+}
+
+impl Steering {
+	pub fn with_max_speed(max_speed: f32) -> Self {
+		Steering {
+			max_speed,
+			turn_rate: std::f32::consts::PI,
+			separation_radius: 12.0,
+			separation_strength: 1.0,
+		}
+	}
+}
+
+// Systems:
+fn update_move_targets(
+	player_query: Query<(&Transform, With<Player>)>,
+	mut enemy_query: Query<(&Transform, &EnemyBehaviour, Option<&FleeAtLowHealth>, Option<&Health>, &mut EnemyMoveTarget)>,
+) {
+	let player_position = player_query.iter().next().map(|(tf, _)| tf.translation.truncate());
+
+	for (transform, behaviour, flee, health, mut move_target) in enemy_query.iter_mut() {
+		let position = transform.translation.truncate();
+		let player_position = match player_position {
+			Some(p) => p,
+			None => {
+				// Nobody to chase.  Stand still.
+				move_target.0 = position;
+				continue;
+			}
+		};
+		let from_player = position - player_position;
+		let away = from_player.normalize_or_zero();
+
+		let fleeing = match (flee, health) {
+			(Some(flee), Some(health)) => health.0 <= flee.0,
+			_ => false,
+		};
+
+		move_target.0 = if fleeing {
+			position + away * FLEE_DISTANCE
+		} else {
+			match *behaviour {
+				EnemyBehaviour::Chase => player_position,
+				EnemyBehaviour::Orbit { radius } => {
+					let angle = from_player.y.atan2(from_player.x) + ORBIT_LEAD_ANGLE;
+					player_position + Vec2::new(angle.cos(), angle.sin()) * radius
+				},
+				EnemyBehaviour::KeepDistance { distance } => player_position + away * distance,
+			}
+		};
+	}
+}
+
+fn steer_enemies(
+	time: Res<Time>,
+	hit_stop: Res<HitStop>,
+	spatial_hash: Res<SpatialHash>,
+	neighbours: Query<&Transform, With<Enemy>>,
+	mut enemy_query: Query<(Entity, &Transform, &Steering, &EnemyMoveTarget, &mut Velocity)>,
+) {
+	let dt = hit_stop.delta_seconds(&time);
+
+	for (entity, transform, steering, move_target, mut velocity) in enemy_query.iter_mut() {
+		let position = transform.translation.truncate();
+
+		// Seek, slowing down on arrival.
+		let to_target = move_target.0 - position;
+		let distance = to_target.length();
+		let arrive_scale = (distance / ARRIVE_DISTANCE).min(1.0);
+		let mut desired = to_target.normalize_or_zero() * steering.max_speed * arrive_scale;
+
+		// Separation.  Push away from close neighbours, harder the closer they are.
+		let mut push = Vec2::ZERO;
+		for other in spatial_hash.query_circle(position, steering.separation_radius, LAYER_ENEMY) {
+			if other == entity {
+				continue;
+			}
+			if let Ok(other_transform) = neighbours.get(other) {
+				let offset = position - other_transform.translation.truncate();
+				let overlap = 1.0 - (offset.length() / steering.separation_radius).min(1.0);
+				push += offset.normalize_or_zero() * overlap;
+			}
+		}
+		desired += push * steering.max_speed * steering.separation_strength;
+		if desired.length() > steering.max_speed {
+			desired = desired.normalize() * steering.max_speed;
+		}
+
+		// Turn toward the desired heading no faster than turn_rate.  Speed changes immediately.
+		let current = velocity.0.truncate();
+		let new_velocity = if current.length_squared() < 1e-6 || desired.length_squared() < 1e-6 {
+			desired
+		} else {
+			let current_angle = current.y.atan2(current.x);
+			let desired_angle = desired.y.atan2(desired.x);
+			let mut delta = desired_angle - current_angle;
+			// Wrap to [-PI, PI] so we turn the short way around.
+			while delta > std::f32::consts::PI {
+				delta -= 2.0 * std::f32::consts::PI;
+			}
+			while delta < -std::f32::consts::PI {
+				delta += 2.0 * std::f32::consts::PI;
+			}
+			let max_turn = steering.turn_rate * dt;
+			let angle = current_angle + delta.clamp(-max_turn, max_turn);
+			Vec2::new(angle.cos(), angle.sin()) * desired.length()
+		};
+		velocity.0.x = new_velocity.x;
+		velocity.0.y = new_velocity.y;
+	}
+}
@@ -0,0 +1,110 @@
+use bevy::asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use rand::{Rng, thread_rng};
+use serde::Deserialize;
+
+use crate::SpriteDefinition;
+use crate::damage::Resistances;
+use crate::enemy_ai::EnemyBehaviour;
+use crate::projectile::EmitterSpec;
+
+pub const ENEMY_ARCHETYPES_PATH: &str = "enemies.archetypes.ron";
+
+// Assets:
+// Every kind of enemy is described in assets/enemies.archetypes.ron.  Adding a new one should never need a new system.
+#[derive(Debug, Deserialize, TypeUuid)]
+#[uuid = "b0f1a6a4-2b8e-4f7e-9d3c-6c1e5a8f0d21"]
+pub struct EnemyArchetypeSet {
+	pub archetypes: Vec<EnemyArchetype>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EnemyArchetype {
+	pub name: String,
+	pub sprite: SpriteDefinition,
+	pub health: f32,
+	pub speed: f32,
+	#[serde(default = "default_turn_rate")]
+	pub turn_rate: f32, // Radians per second.
+	pub contact_damage: f32,
+	#[serde(default)]
+	pub resistances: Resistances,
+	pub score: u32,
+	pub behaviour: EnemyBehaviour,
+	#[serde(default)]
+	pub flee_below_health: Option<f32>,
+	pub spawn_weight: f32, // Relative.  0 = never picked at random, but can still be spawned by name (splits, scripts).
+	#[serde(default)]
+	pub min_wave: u32, // Don't show up before this wave.
+	#[serde(default)]
+	pub splits_into: Option<EnemySplit>,
+	#[serde(default)]
+	pub emitter: Option<EmitterSpec>, // Ranged enemies.  Fires at the player on a timer.
+	#[serde(skip)]
+	pub atlas: Handle<TextureAtlas>, // Built by the loader from `sprite`.
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct EnemySplit {
+	pub archetype: String,
+	pub count: u32,
+}
+
+fn default_turn_rate() -> f32 {
+	std::f32::consts::PI
+}
+
+impl EnemyArchetypeSet {
+	pub fn get(&self, name: &str) -> Option<&EnemyArchetype> {
+		self.archetypes.iter().find(|a| a.name == name)
+	}
+
+	// Weighted random pick from everything allowed on this wave.
+	pub fn pick_for_wave(&self, wave: u32) -> Option<&EnemyArchetype> {
+		let candidates: Vec<&EnemyArchetype> = self.archetypes.iter().filter(|a| a.min_wave <= wave && a.spawn_weight > 0.0).collect();
+		let total_weight: f32 = candidates.iter().map(|a| a.spawn_weight).sum();
+		if candidates.is_empty() || total_weight <= 0.0 {
+			return None;
+		}
+
+		let mut roll = thread_rng().next_f32() * total_weight;
+		for archetype in candidates.iter() {
+			if roll < archetype.spawn_weight {
+				return Some(archetype);
+			}
+			roll -= archetype.spawn_weight;
+		}
+		candidates.last().cloned() // Float rounding.
+	}
+}
+
+#[derive(Default)]
+pub struct EnemyArchetypeLoader;
+
+impl AssetLoader for EnemyArchetypeLoader {
+	fn load<'a>(
+		&'a self,
+		bytes: &'a [u8],
+		load_context: &'a mut LoadContext,
+	) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+		Box::pin(async move {
+			let mut set: EnemyArchetypeSet = ron::de::from_bytes(bytes)?;
+			let mut dependencies = Vec::new();
+			for archetype in set.archetypes.iter_mut() {
+				let (atlas, texture_path) = archetype.sprite.load_atlas(load_context, &format!("atlas/{}", archetype.name));
+				archetype.atlas = atlas;
+				dependencies.push(texture_path);
+			}
+			load_context.set_default_asset(LoadedAsset::new(set).with_dependencies(dependencies));
+			Ok(())
+		})
+	}
+
+	fn extensions(&self) -> &[&str] {
+		&["archetypes.ron"]
+	}
+}
+
+// Resources:
+pub struct EnemyArchetypes(pub Handle<EnemyArchetypeSet>);
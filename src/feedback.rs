@@ -0,0 +1,250 @@
+use bevy::prelude::*;
+use rand::{Rng, thread_rng};
+use std::time::Duration;
+
+use crate::{Health, Lifetime, Velocity};
+use crate::damage::{DamageEvent, DamageSystem};
+use crate::game_state::AppState;
+
+const NUMBER_FONT: &str = "OpenSans-Regular.ttf";
+const NUMBER_FONT_SIZE: f32 = 8.0; // World units.  The camera doubles it.
+const CRIT_FONT_SIZE: f32 = 13.0;
+const NUMBER_RENDER_PRIORITY: f32 = 5.0; // Over everything in the world.
+const NUMBER_LIFETIME: f32 = 0.6;
+const NUMBER_RISE_SPEED: f32 = 30.0;
+const NUMBER_JITTER: f32 = 6.0; // So a pile of hits on one spot doesn't stack into one unreadable number.
+const HIT_FLASH_BRIGHTNESS: f32 = 8.0; // Sprite colour is a multiply, so white does nothing.  Blowing it out past 1 clamps to white.
+
+// Hit feedback: damage numbers, a flash on whatever got hit, and a moment of hit-stop on the big ones.
+// All of it comes off DamageEvent, so turning any piece off is just a setting and nothing that deals damage has to care.
+pub struct FeedbackPlugin;
+
+impl Plugin for FeedbackPlugin {
+	fn build(&self, app: &mut App) {
+		app.insert_resource(FeedbackSettings::default());
+		app.insert_resource(HitStop::default());
+		app.add_system_set(
+			SystemSet::on_update(AppState::Playing)
+				.with_system(spawn_damage_numbers)
+				.with_system(fade_damage_numbers)
+				.with_system(flash_on_hit.after(DamageSystem))
+				.with_system(update_hit_flashes)
+				.with_system(start_hit_stop)
+				.with_system(tick_hit_stop)
+		);
+		app.add_system_set(SystemSet::on_exit(AppState::Playing).with_system(end_hit_stop));
+	}
+}
+
+// Resources:
+// The accessibility toggles.  Everything defaults on.
+pub struct FeedbackSettings {
+	pub damage_numbers: bool,
+	pub hit_flash: bool,
+	pub hit_stop: bool,
+	pub screen_shake_intensity: f32, // Scales every camera shake.  0 turns it off.
+	pub flash_seconds: f32,
+	pub heavy_hit_damage: f32, // Hits at least this big, and every crit, get hit-stop.
+	pub hit_stop_seconds: f32, // Real time, not dilated time.
+	pub hit_stop_time_scale: f32, // How fast the world moves during hit-stop.
+}
+
+impl Default for FeedbackSettings {
+	fn default() -> Self {
+		FeedbackSettings {
+			damage_numbers: true,
+			hit_flash: true,
+			hit_stop: true,
+			screen_shake_intensity: 1.0,
+			flash_seconds: 0.08,
+			heavy_hit_damage: 2.0,
+			hit_stop_seconds: 0.06,
+			hit_stop_time_scale: 0.05,
+		}
+	}
+}
+
+// Bevy's Time can't be slowed down, so everything that runs on game time scales its delta by this: movement, animation,
+// particles, and every gameplay timer (emitters, boss attacks, spawning, cooldowns, mana, lifetimes...).
+// UI, the camera and the feedback itself stay on real time.  Use delta()/delta_seconds() rather than Time's.
+#[derive(Default)]
+pub struct HitStop {
+	remaining: f32,
+	time_scale: f32,
+}
+
+impl HitStop {
+	pub fn time_scale(&self) -> f32 {
+		if self.remaining > 0.0 { self.time_scale } else { 1.0 }
+	}
+
+	pub fn delta(&self, time: &Time) -> Duration {
+		time.delta().mul_f32(self.time_scale())
+	}
+
+	pub fn delta_seconds(&self, time: &Time) -> f32 {
+		time.delta_seconds() * self.time_scale()
+	}
+}
+
+// Components:
+#[derive(Component)]
+struct DamageNumber {
+	color: Color,
+}
+
+#[derive(Component)]
+struct HitFlash {
+	timer: Timer,
+	original_color: Color,
+}
+
+// Systems:
+fn spawn_damage_numbers(
+	mut commands: Commands,
+	settings: Res<FeedbackSettings>,
+	asset_server: Res<AssetServer>,
+	mut damage_events: EventReader<DamageEvent>,
+) {
+	if !settings.damage_numbers {
+		return;
+	}
+	let mut rng = thread_rng();
+	for event in damage_events.iter() {
+		// Resisted down to nothing?  Don't draw a zero.
+		if event.amount <= 0.0 {
+			continue;
+		}
+		let color = event.element.color();
+		let (text, font_size) = if event.critical {
+			(format!("{}!", format_damage(event.amount)), CRIT_FONT_SIZE)
+		} else {
+			(format_damage(event.amount), NUMBER_FONT_SIZE)
+		};
+		let jitter = Vec2::new(rng.gen_range(-NUMBER_JITTER, NUMBER_JITTER), rng.gen_range(-NUMBER_JITTER, NUMBER_JITTER));
+
+		commands
+			.spawn_bundle(Text2dBundle {
+				text: Text::with_section(
+					text,
+					TextStyle {
+						font: asset_server.load(NUMBER_FONT),
+						font_size,
+						color,
+					},
+					TextAlignment {
+						horizontal: HorizontalAlign::Center,
+						vertical: VerticalAlign::Center,
+					},
+				),
+				transform: Transform::from_translation((event.position + jitter).extend(NUMBER_RENDER_PRIORITY)),
+				..Default::default()
+			})
+			.insert(DamageNumber { color })
+			.insert(Velocity(Vec3::new(jitter.x, NUMBER_RISE_SPEED, 0.0)))
+			.insert(Lifetime(Timer::from_seconds(NUMBER_LIFETIME, false)));
+	}
+}
+
+// Most damage is whole numbers, but splash and resistances make halves.
+fn format_damage(amount: f32) -> String {
+	if (amount - amount.round()).abs() < 0.05 {
+		format!("{:.0}", amount)
+	} else {
+		format!("{:.1}", amount)
+	}
+}
+
+fn fade_damage_numbers(
+	mut query: Query<(&DamageNumber, &Lifetime, &mut Text)>,
+) {
+	for (number, lifetime, mut text) in query.iter_mut() {
+		// Hold for the first half, then fade out.
+		let fade = (2.0 - 2.0 * lifetime.0.percent()).min(1.0);
+		let mut color = number.color;
+		color.set_a(fade);
+		text.sections[0].style.color = color;
+	}
+}
+
+fn flash_on_hit(
+	mut commands: Commands,
+	settings: Res<FeedbackSettings>,
+	mut damage_events: EventReader<DamageEvent>,
+	mut sprite_query: Query<(&mut TextureAtlasSprite, Option<&mut HitFlash>, Option<&Health>)>,
+) {
+	if !settings.hit_flash {
+		return;
+	}
+	// The HitFlash insert doesn't land until the end of the frame, so a second hit this frame would see no flash yet.
+	let mut flashed = Vec::new();
+	for event in damage_events.iter() {
+		if flashed.contains(&event.target) {
+			continue;
+		}
+		flashed.push(event.target);
+		let (mut sprite, flash, health) = match sprite_query.get_mut(event.target) {
+			Ok(target) => target,
+			Err(_) => continue, // Already gone.
+		};
+		// Killed by this hit (we run after the damage is applied), so it's being despawned this frame.  Inserting onto it after that would panic.
+		if health.map_or(false, |health| health.0 <= 0.0) {
+			continue;
+		}
+		match flash {
+			// Already flashing.  Start over, but don't save our own white as the colour to go back to.
+			Some(mut flash) => flash.timer.reset(),
+			None => {
+				commands.entity(event.target).insert(HitFlash {
+					timer: Timer::from_seconds(settings.flash_seconds, false),
+					original_color: sprite.color,
+				});
+				sprite.color = Color::rgb(HIT_FLASH_BRIGHTNESS, HIT_FLASH_BRIGHTNESS, HIT_FLASH_BRIGHTNESS);
+			},
+		}
+	}
+}
+
+fn update_hit_flashes(
+	mut commands: Commands,
+	time: Res<Time>,
+	mut query: Query<(Entity, &mut HitFlash, &mut TextureAtlasSprite)>,
+) {
+	for (entity, mut flash, mut sprite) in query.iter_mut() {
+		flash.timer.tick(time.delta());
+		if flash.timer.finished() {
+			sprite.color = flash.original_color;
+			commands.entity(entity).remove::<HitFlash>();
+		}
+	}
+}
+
+fn start_hit_stop(
+	settings: Res<FeedbackSettings>,
+	mut hit_stop: ResMut<HitStop>,
+	mut damage_events: EventReader<DamageEvent>,
+) {
+	if !settings.hit_stop {
+		return;
+	}
+	// Doesn't stack.  A shotgun of crits is still one stop.
+	if damage_events.iter().any(|event| event.critical || event.amount >= settings.heavy_hit_damage) {
+		hit_stop.remaining = settings.hit_stop_seconds;
+		hit_stop.time_scale = settings.hit_stop_time_scale;
+	}
+}
+
+fn tick_hit_stop(
+	time: Res<Time>,
+	mut hit_stop: ResMut<HitStop>,
+) {
+	if hit_stop.remaining > 0.0 {
+		hit_stop.remaining -= time.delta_seconds();
+	}
+}
+
+fn end_hit_stop(
+	mut hit_stop: ResMut<HitStop>,
+) {
+	hit_stop.remaining = 0.0;
+}
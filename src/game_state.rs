@@ -0,0 +1,214 @@
+use bevy::asset::LoadState;
+use bevy::prelude::*;
+
+use crate::input::{Action, ActionState};
+use crate::player::RunEnded;
+use crate::ui_text;
+
+const MENU_FONT: &str = "OpenSans-Regular.ttf";
+const OVERLAY_COLOR: Color = Color::rgba(0.0, 0.0, 0.0, 0.6);
+
+// Loading -> MainMenu -> Playing <-> Paused, and Playing -> GameOver -> Playing for a restart.
+// Paused is pushed on top of Playing so nothing gets torn down; everything else is a plain set().
+// Gameplay systems live in `SystemSet::on_update(AppState::Playing)`, so they freeze while paused and stop in menus.
+pub struct GameStatePlugin;
+
+impl Plugin for GameStatePlugin {
+	fn build(&self, app: &mut App) {
+		app.add_state(AppState::Loading);
+		app.insert_resource(LoadingAssets::default());
+		app.add_system_set(SystemSet::on_update(AppState::Loading).with_system(wait_for_assets));
+
+		app.add_system_set(SystemSet::on_enter(AppState::MainMenu).with_system(spawn_main_menu));
+		app.add_system_set(SystemSet::on_update(AppState::MainMenu).with_system(start_from_menu));
+		app.add_system_set(SystemSet::on_exit(AppState::MainMenu).with_system(despawn_menu_ui));
+
+		app.add_system_set(SystemSet::on_enter(AppState::Playing).with_system(announce_run_start));
+		app.add_system_set(
+			SystemSet::on_update(AppState::Playing)
+				.with_system(pause_game)
+				.with_system(end_run)
+		);
+
+		app.add_system_set(SystemSet::on_enter(AppState::Paused).with_system(spawn_pause_menu));
+		app.add_system_set(SystemSet::on_update(AppState::Paused).with_system(resume_game));
+		app.add_system_set(SystemSet::on_exit(AppState::Paused).with_system(despawn_menu_ui));
+
+		app.add_system_set(SystemSet::on_enter(AppState::GameOver).with_system(spawn_game_over));
+		app.add_system_set(SystemSet::on_update(AppState::GameOver).with_system(restart_from_game_over));
+		app.add_system_set(SystemSet::on_exit(AppState::GameOver).with_system(despawn_menu_ui));
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AppState {
+	Loading,
+	MainMenu,
+	Playing,
+	Paused,
+	GameOver,
+}
+
+// Resources:
+// Anything that has to be ready before the title screen shows up.  Plugins add their handles in their startup systems.
+#[derive(Default)]
+pub struct LoadingAssets(pub Vec<HandleUntyped>);
+
+// Components:
+// Every menu/overlay entity.  Only one screen is ever up at a time, so leaving any of them clears the lot.
+#[derive(Component)]
+struct MenuUi;
+
+// Systems:
+fn wait_for_assets(
+	mut state: ResMut<State<AppState>>,
+	asset_server: Res<AssetServer>,
+	loading: Res<LoadingAssets>,
+) {
+	match asset_server.get_group_load_state(loading.0.iter().map(|handle| handle.id)) {
+		LoadState::Loaded => {},
+		LoadState::Failed => {
+			// Everything that reads these assets copes with them missing, so carry on and let the warnings speak.
+			warn!("Some assets failed to load.  Continuing anyway.");
+		},
+		_ => return,
+	}
+	if let Err(e) = state.set(AppState::MainMenu) {
+		warn!("Couldn't leave the loading screen: {:?}", e);
+	}
+}
+
+fn start_from_menu(
+	mut state: ResMut<State<AppState>>,
+	mut actions: ResMut<ActionState>,
+) {
+	if actions.consume_just_pressed(Action::CastPrimary) || actions.consume_just_pressed(Action::Pause) {
+		if let Err(e) = state.set(AppState::Playing) {
+			warn!("Couldn't start the game: {:?}", e);
+		}
+	}
+}
+
+fn pause_game(
+	mut state: ResMut<State<AppState>>,
+	mut actions: ResMut<ActionState>,
+) {
+	if actions.consume_just_pressed(Action::Pause) {
+		if let Err(e) = state.push(AppState::Paused) {
+			warn!("Couldn't pause: {:?}", e);
+		}
+	}
+}
+
+fn end_run(
+	mut state: ResMut<State<AppState>>,
+	mut run_ended_events: EventReader<RunEnded>,
+) {
+	if run_ended_events.iter().next().is_some() {
+		if let Err(e) = state.set(AppState::GameOver) {
+			warn!("Couldn't end the run: {:?}", e);
+		}
+	}
+}
+
+fn resume_game(
+	mut state: ResMut<State<AppState>>,
+	mut actions: ResMut<ActionState>,
+) {
+	if actions.consume_just_pressed(Action::Pause) {
+		if let Err(e) = state.pop() {
+			warn!("Couldn't unpause: {:?}", e);
+		}
+	}
+}
+
+fn restart_from_game_over(
+	mut state: ResMut<State<AppState>>,
+	mut actions: ResMut<ActionState>,
+) {
+	if actions.consume_just_pressed(Action::CastPrimary) {
+		if let Err(e) = state.set(AppState::Playing) {
+			warn!("Couldn't restart: {:?}", e);
+		}
+	} else if actions.consume_just_pressed(Action::Pause) {
+		if let Err(e) = state.set(AppState::MainMenu) {
+			warn!("Couldn't go back to the title: {:?}", e);
+		}
+	}
+}
+
+fn announce_run_start(
+	mut ui_text_events: EventWriter<ui_text::UIText>,
+) {
+	ui_text_events.send(ui_text::UIText::from_string("You're a Heckin' Wizard!  Fight!".to_string()));
+}
+
+fn spawn_main_menu(
+	mut commands: Commands,
+	asset_server: Res<AssetServer>,
+) {
+	spawn_menu_screen(&mut commands, &asset_server, "Heckin' Wizard", "Click or press Escape to start");
+}
+
+fn spawn_pause_menu(
+	mut commands: Commands,
+	asset_server: Res<AssetServer>,
+) {
+	spawn_menu_screen(&mut commands, &asset_server, "Paused", "Press Escape to resume");
+}
+
+fn spawn_game_over(
+	mut commands: Commands,
+	asset_server: Res<AssetServer>,
+) {
+	spawn_menu_screen(&mut commands, &asset_server, "Game Over", "Click to try again, or Escape for the title screen");
+}
+
+fn despawn_menu_ui(
+	mut commands: Commands,
+	menu_query: Query<(Entity, With<MenuUi>)>,
+) {
+	for (entity, _) in menu_query.iter() {
+		commands.entity(entity).despawn_recursive();
+	}
+}
+
+// A dimmed full-screen panel with a big title and a hint underneath.
+fn spawn_menu_screen(
+	commands: &mut Commands,
+	asset_server: &AssetServer,
+	title: &str,
+	hint: &str,
+) {
+	let font = asset_server.load(MENU_FONT);
+	commands
+		.spawn_bundle(NodeBundle {
+			style: Style {
+				size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+				position_type: PositionType::Absolute,
+				flex_direction: FlexDirection::ColumnReverse, // Top to bottom.
+				justify_content: JustifyContent::Center,
+				align_items: AlignItems::Center,
+				..Default::default()
+			},
+			color: UiColor(OVERLAY_COLOR),
+			..Default::default()
+		})
+		.insert(MenuUi)
+		.with_children(|parent| {
+			for (text, size) in [(title, 120.0), (hint, 40.0)] {
+				parent.spawn_bundle(TextBundle {
+					text: Text::with_section(
+						text.to_string(),
+						TextStyle {
+							font: font.clone(),
+							font_size: size,
+							color: Color::WHITE,
+						},
+						Default::default()
+					),
+					..Default::default()
+				});
+			}
+		});
+}
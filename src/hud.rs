@@ -0,0 +1,273 @@
+use bevy::prelude::*;
+
+use crate::Health;
+use crate::enemy::{ActiveEnemiesInWave, PendingEnemiesInWave, Wave};
+use crate::game_state::AppState;
+use crate::player::{Lives, Player, PLAYER_HEALTH};
+use crate::score::Score;
+use crate::spells::{Mana, SpellDefinition, Spellbook};
+
+const HUD_FONT: &str = "OpenSans-Regular.ttf";
+const HUD_FONT_SIZE: f32 = 28.0;
+const BAR_WIDTH: f32 = 200.0;
+const BAR_HEIGHT: f32 = 14.0;
+const COOLDOWN_SIZE: f32 = 40.0;
+const BAR_BACKGROUND: Color = Color::rgba(0.0, 0.0, 0.0, 0.6);
+const HEALTH_COLOR: Color = Color::rgb(0.8, 0.15, 0.15);
+const MANA_COLOR: Color = Color::rgb(0.2, 0.4, 0.9);
+const COOLDOWN_COLOR: Color = Color::rgb(0.7, 0.5, 1.0);
+
+// Built once when a run starts and torn down when it ends.  Each piece only gets rewritten when what it shows has changed.
+pub struct HudPlugin;
+
+impl Plugin for HudPlugin {
+	fn build(&self, app: &mut App) {
+		app.add_system_set(SystemSet::on_enter(AppState::Playing).with_system(spawn_hud));
+		app.add_system_set(
+			SystemSet::on_update(AppState::Playing)
+				.with_system(update_health_bar)
+				.with_system(update_mana_bar)
+				.with_system(update_cooldowns)
+				.with_system(update_wave_text)
+				.with_system(update_score_text)
+				.with_system(update_lives_text)
+		);
+		app.add_system_set(SystemSet::on_exit(AppState::Playing).with_system(despawn_hud));
+	}
+}
+
+// Components:
+#[derive(Component)]
+struct HudRoot;
+
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+enum HudBar {
+	Health,
+	Mana,
+	PrimaryCooldown,
+	SecondaryCooldown,
+}
+
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+enum HudText {
+	Wave,
+	Score,
+	Lives,
+}
+
+// Systems:
+fn spawn_hud(
+	mut commands: Commands,
+	asset_server: Res<AssetServer>,
+) {
+	let text_style = TextStyle {
+		font: asset_server.load(HUD_FONT),
+		font_size: HUD_FONT_SIZE,
+		color: Color::WHITE,
+	};
+	let corner = |position: Rect<Val>, direction: FlexDirection| NodeBundle {
+		style: Style {
+			position_type: PositionType::Absolute,
+			position,
+			flex_direction: direction,
+			align_items: AlignItems::FlexStart,
+			..Default::default()
+		},
+		color: UiColor(Color::NONE),
+		..Default::default()
+	};
+
+	// Top left: health, mana, lives.
+	commands
+		.spawn_bundle(corner(Rect { left: Val::Px(16.0), top: Val::Px(16.0), ..Default::default() }, FlexDirection::ColumnReverse))
+		.insert(HudRoot)
+		.with_children(|parent| {
+			spawn_bar(parent, HudBar::Health, HEALTH_COLOR, Size::new(Val::Px(BAR_WIDTH), Val::Px(BAR_HEIGHT)));
+			spawn_bar(parent, HudBar::Mana, MANA_COLOR, Size::new(Val::Px(BAR_WIDTH), Val::Px(BAR_HEIGHT)));
+			spawn_text(parent, HudText::Lives, &text_style);
+		});
+
+	// Top middle: wave and what's left of it.
+	commands
+		.spawn_bundle(corner(Rect { left: Val::Percent(45.0), top: Val::Px(8.0), ..Default::default() }, FlexDirection::ColumnReverse))
+		.insert(HudRoot)
+		.with_children(|parent| {
+			spawn_text(parent, HudText::Wave, &text_style);
+		});
+
+	// Top right: score and combo.
+	commands
+		.spawn_bundle(corner(Rect { right: Val::Px(16.0), top: Val::Px(8.0), ..Default::default() }, FlexDirection::ColumnReverse))
+		.insert(HudRoot)
+		.with_children(|parent| {
+			spawn_text(parent, HudText::Score, &text_style);
+		});
+
+	// Bottom left: spell cooldowns.  They fill back up as the spell becomes ready.
+	commands
+		.spawn_bundle(corner(Rect { left: Val::Px(16.0), bottom: Val::Px(16.0), ..Default::default() }, FlexDirection::Row))
+		.insert(HudRoot)
+		.with_children(|parent| {
+			spawn_bar(parent, HudBar::PrimaryCooldown, COOLDOWN_COLOR, Size::new(Val::Px(COOLDOWN_SIZE), Val::Px(COOLDOWN_SIZE)));
+			spawn_bar(parent, HudBar::SecondaryCooldown, COOLDOWN_COLOR, Size::new(Val::Px(COOLDOWN_SIZE), Val::Px(COOLDOWN_SIZE)));
+		});
+}
+
+fn spawn_bar(
+	parent: &mut ChildBuilder,
+	bar: HudBar,
+	color: Color,
+	size: Size<Val>,
+) {
+	parent
+		.spawn_bundle(NodeBundle {
+			style: Style {
+				size,
+				margin: Rect::all(Val::Px(2.0)),
+				..Default::default()
+			},
+			color: UiColor(BAR_BACKGROUND),
+			..Default::default()
+		})
+		.with_children(|background| {
+			background
+				.spawn_bundle(NodeBundle {
+					style: Style {
+						size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+						..Default::default()
+					},
+					color: UiColor(color),
+					..Default::default()
+				})
+				.insert(bar);
+		});
+}
+
+fn spawn_text(
+	parent: &mut ChildBuilder,
+	text: HudText,
+	style: &TextStyle,
+) {
+	parent
+		.spawn_bundle(TextBundle {
+			text: Text::with_section(String::new(), style.clone(), Default::default()),
+			..Default::default()
+		})
+		.insert(text);
+}
+
+fn set_bar(
+	bars: &mut Query<(&HudBar, &mut Style)>,
+	which: HudBar,
+	fraction: f32,
+) {
+	for (bar, mut style) in bars.iter_mut() {
+		if *bar == which {
+			style.size.width = Val::Percent(fraction.clamp(0.0, 1.0) * 100.0);
+		}
+	}
+}
+
+fn set_text(
+	texts: &mut Query<(&HudText, &mut Text)>,
+	which: HudText,
+	value: String,
+) {
+	for (text, mut section_text) in texts.iter_mut() {
+		// Writing the same string would still count as a change and re-layout the text.
+		if *text == which && section_text.sections[0].value != value {
+			section_text.sections[0].value = value.clone();
+		}
+	}
+}
+
+fn update_health_bar(
+	player_query: Query<&Health, (With<Player>, Changed<Health>)>,
+	removed_players: RemovedComponents<Player>,
+	mut bars: Query<(&HudBar, &mut Style)>,
+) {
+	// Changed includes added, so a fresh spawn refills the bar.
+	if let Some(health) = player_query.iter().next() {
+		set_bar(&mut bars, HudBar::Health, health.0 / PLAYER_HEALTH);
+	} else if removed_players.iter().next().is_some() {
+		set_bar(&mut bars, HudBar::Health, 0.0);
+	}
+}
+
+fn update_mana_bar(
+	mana_query: Query<&Mana, (With<Player>, Changed<Mana>)>,
+	mut bars: Query<(&HudBar, &mut Style)>,
+) {
+	if let Some(mana) = mana_query.iter().next() {
+		set_bar(&mut bars, HudBar::Mana, mana.current / mana.max.max(1e-6));
+	}
+}
+
+fn update_cooldowns(
+	spellbook: Res<Spellbook>,
+	spells: Res<Assets<SpellDefinition>>,
+	new_bars: Query<Entity, Added<HudBar>>,
+	mut bars: Query<(&HudBar, &mut Style)>,
+) {
+	if !spellbook.is_changed() && new_bars.iter().next().is_none() {
+		return;
+	}
+	for (slot, bar) in [(&spellbook.primary, HudBar::PrimaryCooldown), (&spellbook.secondary, HudBar::SecondaryCooldown)] {
+		let cooldown = spells.get(&slot.spell).map(|spell| spell.cooldown).unwrap_or(0.0);
+		let ready = if cooldown > 0.0 { 1.0 - slot.cooldown_remaining / cooldown } else { 1.0 };
+		set_bar(&mut bars, bar, ready);
+	}
+}
+
+fn update_wave_text(
+	wave: Res<Wave>,
+	active_enemies: Res<ActiveEnemiesInWave>,
+	pending_enemies: Res<PendingEnemiesInWave>,
+	new_texts: Query<Entity, Added<HudText>>,
+	mut texts: Query<(&HudText, &mut Text)>,
+) {
+	if !(wave.is_changed() || active_enemies.is_changed() || pending_enemies.is_changed()) && new_texts.iter().next().is_none() {
+		return;
+	}
+	let remaining = active_enemies.0 + pending_enemies.0;
+	set_text(&mut texts, HudText::Wave, format!("Wave {}  -  {} left", wave.0, remaining));
+}
+
+fn update_score_text(
+	score: Res<Score>,
+	new_texts: Query<Entity, Added<HudText>>,
+	mut texts: Query<(&HudText, &mut Text)>,
+) {
+	if !score.is_changed() && new_texts.iter().next().is_none() {
+		return;
+	}
+	let line = if score.combo > 1 {
+		format!("{}  x{:.1} ({} combo)", score.points, score.multiplier, score.combo)
+	} else if score.multiplier > 1.0 {
+		format!("{}  x{:.1}", score.points, score.multiplier)
+	} else {
+		format!("{}", score.points)
+	};
+	set_text(&mut texts, HudText::Score, line);
+}
+
+fn update_lives_text(
+	lives: Res<Lives>,
+	new_texts: Query<Entity, Added<HudText>>,
+	mut texts: Query<(&HudText, &mut Text)>,
+) {
+	// The HUD is spawned through commands, so it can show up a frame after the resources last changed.
+	if !lives.is_changed() && new_texts.iter().next().is_none() {
+		return;
+	}
+	set_text(&mut texts, HudText::Lives, format!("Lives: {}", lives.0));
+}
+
+fn despawn_hud(
+	mut commands: Commands,
+	roots: Query<(Entity, With<HudRoot>)>,
+) {
+	for (entity, _) in roots.iter() {
+		commands.entity(entity).despawn_recursive();
+	}
+}
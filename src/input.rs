@@ -1,112 +1,660 @@
+use bevy::asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset};
 use bevy::prelude::*;
-use bevy::input::gamepad::Gamepad;
-use bevy::input::touch::*;
-use bevy::input::mouse::{MouseButtonInput, MouseMotion, MouseWheel};
-use bevy::window::CursorMoved;
+use bevy::input::InputSystem;
+use bevy::input::touch::Touches;
+use bevy::reflect::TypeUuid;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 
-pub fn mouse_click_system(
-	mut commands: Commands,
-	mouse_button_input: Res<Input<MouseButton>>,
-) {
-	if mouse_button_input.pressed(MouseButton::Left) {
-		//info!("left mouse currently pressed");
+use crate::{GameplayCamera, user_data_path};
+use crate::game_state::LoadingAssets;
+
+const DEFAULT_BINDINGS_PATH: &str = "input.bindings.ron"; // The shipped defaults, through the asset server.
+const USER_BINDINGS_FILE: &str = "input_bindings.ron"; // The player's rebinds, in the user data directory.
+
+// Gameplay systems should read ActionState and never touch Input<KeyCode>/Input<MouseButton>/etc. directly.
+// That way we can add or remap controls without going into spell or player code.
+pub struct InputPlugin;
+
+impl Plugin for InputPlugin {
+	fn build(&self, app: &mut App) {
+		app.add_asset::<InputBindings>();
+		app.init_asset_loader::<InputBindingsLoader>();
+		// Rebinds win outright.  Without any, use the built-in defaults until the shipped file loads.
+		let user_bindings = InputBindings::load(&user_bindings_path());
+		app.insert_resource(UserBindings(user_bindings.is_some()));
+		app.insert_resource(user_bindings.unwrap_or_default());
+		app.add_startup_system(load_default_bindings);
+		app.add_system(apply_default_bindings);
+		app.insert_resource(ActionState::default());
+		app.insert_resource(PendingRebind(None));
+		app.insert_resource(CursorWorldPosition::default());
+		app.insert_resource(TouchControls::default());
+		// Run right after Bevy updates the raw device state so everything in Update sees this frame's actions.
+		app.add_system_to_stage(CoreStage::PreUpdate, listen_for_rebind.after(InputSystem));
+		app.add_system_to_stage(CoreStage::PreUpdate, update_touch_controls.after(InputSystem).before(ActionSystem));
+		app.add_system_to_stage(CoreStage::PreUpdate, update_action_state.label(ActionSystem).after(InputSystem));
+		app.add_system_to_stage(CoreStage::PreUpdate, update_cursor_world_position.after(ActionSystem));
+		#[cfg(debug_assertions)]
+		app.add_system(debug_start_rebind);
 	}
+}
+
+#[derive(SystemLabel, Clone, Hash, Debug, PartialEq, Eq)]
+pub struct ActionSystem;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+	MoveUp,
+	MoveDown,
+	MoveLeft,
+	MoveRight,
+	CastPrimary,
+	CastSecondary,
+	Pause,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum InputBinding {
+	Key(KeyCode),
+	Mouse(MouseButton),
+	Gamepad(GamepadButtonType),
+	Touch, // A finger on the casting (right) half of the screen.
+}
+
+// Where the player is pointing.  Mouse and touch give us a spot in window space, a stick gives a direction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Aim {
+	None,
+	Cursor(Vec2),
+	Direction(Vec2),
+}
 
-	if mouse_button_input.just_pressed(MouseButton::Left) {
+impl Default for Aim {
+	fn default() -> Self {
+		Aim::None
 	}
+}
+
+// Assets / Resources:
+// The same type is both.  assets/input.bindings.ron loads as an asset and gets copied into the resource,
+// unless the player has rebinds of their own.
+#[derive(Clone, Debug, Serialize, Deserialize, TypeUuid)]
+#[uuid = "e3a7c4d2-5f18-4b6a-9c0e-1d2b8f7a6e35"]
+pub struct InputBindings {
+	pub actions: HashMap<Action, Vec<InputBinding>>,
+	pub move_axes: (GamepadAxisType, GamepadAxisType), // X, Y
+	pub aim_axes: (GamepadAxisType, GamepadAxisType), // X, Y
+	pub gamepad_deadzone: f32,
+}
 
-	if mouse_button_input.just_released(MouseButton::Left) {
+impl Default for InputBindings {
+	fn default() -> Self {
+		let mut actions = HashMap::new();
+		actions.insert(Action::MoveUp, vec![InputBinding::Key(KeyCode::W), InputBinding::Key(KeyCode::Up), InputBinding::Gamepad(GamepadButtonType::DPadUp)]);
+		actions.insert(Action::MoveDown, vec![InputBinding::Key(KeyCode::S), InputBinding::Key(KeyCode::Down), InputBinding::Gamepad(GamepadButtonType::DPadDown)]);
+		actions.insert(Action::MoveLeft, vec![InputBinding::Key(KeyCode::A), InputBinding::Key(KeyCode::Left), InputBinding::Gamepad(GamepadButtonType::DPadLeft)]);
+		actions.insert(Action::MoveRight, vec![InputBinding::Key(KeyCode::D), InputBinding::Key(KeyCode::Right), InputBinding::Gamepad(GamepadButtonType::DPadRight)]);
+		actions.insert(Action::CastPrimary, vec![InputBinding::Mouse(MouseButton::Left), InputBinding::Gamepad(GamepadButtonType::RightTrigger2), InputBinding::Touch]);
+		actions.insert(Action::CastSecondary, vec![InputBinding::Mouse(MouseButton::Right), InputBinding::Gamepad(GamepadButtonType::LeftTrigger2)]);
+		actions.insert(Action::Pause, vec![InputBinding::Key(KeyCode::Escape), InputBinding::Gamepad(GamepadButtonType::Start)]);
+
+		InputBindings {
+			actions,
+			move_axes: (GamepadAxisType::LeftStickX, GamepadAxisType::LeftStickY),
+			aim_axes: (GamepadAxisType::RightStickX, GamepadAxisType::RightStickY),
+			gamepad_deadzone: 0.15,
+		}
 	}
 }
 
-pub fn touch_system(touches: Res<Touches>) {
-	for touch in touches.iter_just_pressed() {
-		info!(
-            "just pressed touch with id: {:?}, at: {:?}",
-            touch.id(),
-            touch.position()
-        );
+impl InputBindings {
+	// The player's saved rebinds, if they have any.  A broken file gets complained about and ignored.
+	pub fn load(path: &Path) -> Option<Self> {
+		let contents = std::fs::read_to_string(path).ok()?; // No rebinds yet.  Nothing to complain about.
+		match ron::de::from_str(&contents) {
+			Ok(bindings) => Some(bindings),
+			Err(e) => {
+				warn!("Failed to parse input bindings in {:?}: {}.  Using defaults.", path, e);
+				None
+			}
+		}
 	}
 
-	for touch in touches.iter_just_released() {
-		info!(
-            "just released touch with id: {:?}, at: {:?}",
-            touch.id(),
-            touch.position()
-        );
+	pub fn save(&self, path: &Path) -> Result<(), String> {
+		if let Some(dir) = path.parent() {
+			std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+		}
+		let contents = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()).map_err(|e| e.to_string())?;
+		std::fs::write(path, contents).map_err(|e| e.to_string())
+	}
+
+	pub fn bind(&mut self, action: Action, binding: InputBinding) {
+		let bindings = self.actions.entry(action).or_insert_with(Vec::new);
+		if !bindings.contains(&binding) {
+			bindings.push(binding);
+		}
 	}
 
-	for touch in touches.iter_just_cancelled() {
-		info!("cancelled touch with id: {:?}", touch.id());
+	pub fn unbind(&mut self, action: Action, binding: InputBinding) {
+		if let Some(bindings) = self.actions.get_mut(&action) {
+			bindings.retain(|b| *b != binding);
+		}
 	}
 
-	// you can also iterate all current touches and retrieve their state like this:
-	for touch in touches.iter() {
-		info!("active touch: {:?}", touch);
-		info!("  just_pressed: {}", touches.just_pressed(touch.id()));
+	// Swap out everything on an action for a single binding.  This is what a 'press a key to rebind' menu wants.
+	pub fn rebind(&mut self, action: Action, binding: InputBinding) {
+		self.actions.insert(action, vec![binding]);
 	}
 }
 
-/// This system prints out all mouse events as they come in
-pub fn input_event_system(
-	mut mouse_button_input_events: EventReader<MouseButtonInput>,
-	mut mouse_motion_events: EventReader<MouseMotion>,
-	mut cursor_moved_events: EventReader<CursorMoved>,
-	mut mouse_wheel_events: EventReader<MouseWheel>,
-	mut touch_events: EventReader<TouchInput>,
-) {
-	for event in mouse_button_input_events.iter() {
+fn user_bindings_path() -> PathBuf {
+	user_data_path(USER_BINDINGS_FILE)
+}
+
+#[derive(Default)]
+pub struct InputBindingsLoader;
+
+impl AssetLoader for InputBindingsLoader {
+	fn load<'a>(
+		&'a self,
+		bytes: &'a [u8],
+		load_context: &'a mut LoadContext,
+	) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+		Box::pin(async move {
+			let bindings: InputBindings = ron::de::from_bytes(bytes)?;
+			load_context.set_default_asset(LoadedAsset::new(bindings));
+			Ok(())
+		})
 	}
 
-	for event in mouse_motion_events.iter() {
-		// Has event.delta as vec2
+	fn extensions(&self) -> &[&str] {
+		&["bindings.ron"]
 	}
+}
+
+struct DefaultBindingsHandle(Handle<InputBindings>);
+
+// True once the player has rebinds saved, so the shipped defaults stop overwriting the resource.
+struct UserBindings(bool);
 
-	for event in cursor_moved_events.iter() {
-		// event.position as Vec2
-		//dbg!("{}", &event);
+// Where the pointer (mouse or touch) is in world space.  None when it's off the window or we're aiming with a stick.
+// Anything that aims or hovers should read this rather than doing its own window math.
+#[derive(Default)]
+pub struct CursorWorldPosition(pub Option<Vec2>);
+
+// Touch scheme: the first finger down on the left half of the screen becomes a floating stick centered where it landed.
+// The first finger down on the right half casts at wherever it's touching for as long as it's held.
+// Each role tracks its own touch id, so moving and casting at the same time works.
+pub struct TouchControls {
+	pub stick_radius: f32, // In window pixels.  Dragging this far from the stick origin is full speed.
+	pub stick: Option<VirtualStick>,
+	pub cast_touch: Option<(u64, Vec2)>, // Touch id and where it is in window space.
+}
+
+impl Default for TouchControls {
+	fn default() -> Self {
+		TouchControls {
+			stick_radius: 60.0,
+			stick: None,
+			cast_touch: None,
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct VirtualStick {
+	pub touch_id: u64,
+	pub origin: Vec2,
+	pub position: Vec2,
+}
+
+impl VirtualStick {
+	pub fn axis(&self, radius: f32) -> Vec2 {
+		let offset = (self.position - self.origin) / radius.max(1.0);
+		if offset.length_squared() > 1.0 {
+			offset.normalize()
+		} else {
+			offset
+		}
+	}
+}
+
+// Set this to Some(action) and the next button pressed on any device gets bound to that action, and saved.
+// An options screen is the intended way in; until there is one, debug builds can start a rebind with F1-F7 (see debug_start_rebind).
+pub struct PendingRebind(pub Option<Action>);
+
+#[derive(Default)]
+pub struct ActionState {
+	pressed: HashSet<Action>,
+	just_pressed: HashSet<Action>,
+	just_released: HashSet<Action>,
+	ignored: HashSet<Action>, // Held, but doesn't count as pressed until it's let go.  See ignore_until_released.
+	pub move_axis: Vec2, // Length is at most 1.
+	pub aim: Aim,
+}
+
+impl ActionState {
+	pub fn pressed(&self, action: Action) -> bool {
+		self.pressed.contains(&action) && !self.ignored.contains(&action)
 	}
 
-	for event in mouse_wheel_events.iter() {
+	pub fn just_pressed(&self, action: Action) -> bool {
+		self.just_pressed.contains(&action)
 	}
 
-	for event in touch_events.iter() {
-		//debug!("{:?}", event);
+	pub fn just_released(&self, action: Action) -> bool {
+		self.just_released.contains(&action)
+	}
+
+	// For presses that change what's on screen (pause, menus).  Clears the press so whatever runs next this frame
+	// (say, the menu we just opened) doesn't act on it too.
+	pub fn consume_just_pressed(&mut self, action: Action) -> bool {
+		self.just_pressed.remove(&action)
+	}
+
+	// For when a button that's still held came from a different context, like the click that started the game.
+	// It reads as not pressed until it's released and pressed again.
+	pub fn ignore_until_released(&mut self, action: Action) {
+		if self.pressed.contains(&action) {
+			self.ignored.insert(action);
+		}
+	}
+
+	// Lets tests and replays drive the game without real devices.
+	pub fn set_pressed(&mut self, action: Action, pressed: bool) {
+		let was_pressed = self.pressed.contains(&action);
+		if pressed && !was_pressed {
+			self.pressed.insert(action);
+			self.just_pressed.insert(action);
+		} else if !pressed && was_pressed {
+			self.pressed.remove(&action);
+			self.ignored.remove(&action);
+			self.just_released.insert(action);
+		}
 	}
 }
 
-pub fn gamepad_system(
+// Window space has the origin in the bottom left.
+// The projection's left/right/top/bottom follow the window size, so this handles resizes, WINDOW_SCALE, and wherever the camera (or screen shake) has put us.
+pub fn screen_to_world(
+	screen_position: Vec2,
+	window: &Window,
+	camera_transform: &Transform,
+	projection: &OrthographicProjection,
+) -> Vec2 {
+	let u = screen_position.x / window.width();
+	let v = screen_position.y / window.height();
+	let camera_space = Vec3::new(
+		(projection.left + (projection.right - projection.left) * u) * projection.scale,
+		(projection.bottom + (projection.top - projection.bottom) * v) * projection.scale,
+		0.0,
+	);
+	let world = camera_transform.mul_vec3(camera_space);
+	Vec2::new(world.x, world.y)
+}
+
+// Systems:
+fn load_default_bindings(
+	mut commands: Commands,
+	asset_server: Res<AssetServer>,
+	mut loading: ResMut<LoadingAssets>,
+) {
+	let handle: Handle<InputBindings> = asset_server.load(DEFAULT_BINDINGS_PATH);
+	loading.0.push(handle.clone_untyped());
+	commands.insert_resource(DefaultBindingsHandle(handle));
+}
+
+// Also picks up edits to the shipped file while the game is running.
+fn apply_default_bindings(
+	mut asset_events: EventReader<AssetEvent<InputBindings>>,
+	default_handle: Option<Res<DefaultBindingsHandle>>,
+	user_bindings: Res<UserBindings>,
+	assets: Res<Assets<InputBindings>>,
+	mut bindings: ResMut<InputBindings>,
+) {
+	let default_handle = match default_handle {
+		Some(handle) => handle,
+		None => return,
+	};
+	for event in asset_events.iter() {
+		if let AssetEvent::Created { handle } | AssetEvent::Modified { handle } = event {
+			if *handle != default_handle.0 || user_bindings.0 {
+				continue;
+			}
+			if let Some(defaults) = assets.get(handle) {
+				*bindings = defaults.clone();
+			}
+		}
+	}
+}
+
+fn binding_held(
+	binding: &InputBinding,
+	keyboard: &Input<KeyCode>,
+	mouse: &Input<MouseButton>,
+	gamepads: &Gamepads,
+	gamepad_buttons: &Input<GamepadButton>,
+	touch_controls: &TouchControls,
+) -> bool {
+	match binding {
+		InputBinding::Key(key) => keyboard.pressed(*key),
+		InputBinding::Mouse(button) => mouse.pressed(*button),
+		InputBinding::Gamepad(button_type) => gamepads.iter().any(|gamepad| gamepad_buttons.pressed(GamepadButton(*gamepad, *button_type))),
+		InputBinding::Touch => touch_controls.cast_touch.is_some(),
+	}
+}
+
+fn update_action_state(
+	bindings: Res<InputBindings>,
+	mut action_state: ResMut<ActionState>,
+	keyboard: Res<Input<KeyCode>>,
+	mouse: Res<Input<MouseButton>>,
 	gamepads: Res<Gamepads>,
-	button_inputs: Res<Input<GamepadButton>>,
-	button_axes: Res<Axis<GamepadButton>>,
+	gamepad_buttons: Res<Input<GamepadButton>>,
 	axes: Res<Axis<GamepadAxis>>,
+	touch_controls: Res<TouchControls>,
+	windows: Res<Windows>,
 ) {
+	// Digital actions.  Compare against last frame to get just pressed/released.
+	let mut held = HashSet::new();
+	for (action, action_bindings) in bindings.actions.iter() {
+		if action_bindings.iter().any(|b| binding_held(b, &keyboard, &mouse, &gamepads, &gamepad_buttons, &touch_controls)) {
+			held.insert(*action);
+		}
+	}
+	let just_pressed = held.difference(&action_state.pressed).cloned().collect();
+	let just_released = action_state.pressed.difference(&held).cloned().collect();
+	action_state.just_pressed = just_pressed;
+	action_state.just_released = just_released;
+	action_state.ignored.retain(|action| held.contains(action));
+	action_state.pressed = held;
+
+	// Movement.  Digital directions plus any stick that's outside the deadzone.
+	let mut move_axis = Vec2::ZERO;
+	if action_state.pressed(Action::MoveUp) {
+		move_axis.y += 1.0;
+	}
+	if action_state.pressed(Action::MoveDown) {
+		move_axis.y -= 1.0;
+	}
+	if action_state.pressed(Action::MoveLeft) {
+		move_axis.x -= 1.0;
+	}
+	if action_state.pressed(Action::MoveRight) {
+		move_axis.x += 1.0;
+	}
+
+	let read_stick = |gamepad: Gamepad, (x_axis, y_axis): (GamepadAxisType, GamepadAxisType)| {
+		let stick = Vec2::new(
+			axes.get(GamepadAxis(gamepad, x_axis)).unwrap_or(0.0),
+			axes.get(GamepadAxis(gamepad, y_axis)).unwrap_or(0.0),
+		);
+		if stick.length() > bindings.gamepad_deadzone { stick } else { Vec2::ZERO }
+	};
+
+	let mut aim_stick = Vec2::ZERO;
 	for gamepad in gamepads.iter().cloned() {
-		if button_inputs.just_pressed(GamepadButton(gamepad, GamepadButtonType::South)) {
-			info!("{:?} just pressed South", gamepad);
-		} else if button_inputs.just_released(GamepadButton(gamepad, GamepadButtonType::South)) {
-			info!("{:?} just released South", gamepad);
+		move_axis += read_stick(gamepad, bindings.move_axes);
+		aim_stick += read_stick(gamepad, bindings.aim_axes);
+	}
+	if let Some(stick) = touch_controls.stick {
+		move_axis += stick.axis(touch_controls.stick_radius);
+	}
+	// Don't let diagonals or keyboard+stick go faster than max speed, but keep partial stick tilts slow.
+	if move_axis.length_squared() > 1.0 {
+		move_axis = move_axis.normalize();
+	}
+	action_state.move_axis = move_axis;
+
+	// Aim.  A held stick wins, then the casting finger, then the mouse.
+	let cursor = windows.get_primary().and_then(|w| w.cursor_position());
+	action_state.aim = if aim_stick.length_squared() > 0.0 {
+		Aim::Direction(aim_stick.normalize())
+	} else if let Some((_, touch_position)) = touch_controls.cast_touch {
+		Aim::Cursor(touch_position)
+	} else if let Some(position) = cursor {
+		Aim::Cursor(position)
+	} else {
+		Aim::None
+	};
+}
+
+fn update_touch_controls(
+	windows: Res<Windows>,
+	touches: Res<Touches>,
+	mut controls: ResMut<TouchControls>,
+) {
+	let half_width = match windows.get_primary() {
+		Some(window) => window.width() / 2.0,
+		None => return,
+	};
+
+	// Forget any fingers that have lifted or been cancelled.
+	if let Some(stick) = controls.stick {
+		if touches.get_pressed(stick.touch_id).is_none() {
+			controls.stick = None;
+		}
+	}
+	if let Some((cast_id, _)) = controls.cast_touch {
+		if touches.get_pressed(cast_id).is_none() {
+			controls.cast_touch = None;
+		}
+	}
+
+	// Assign new fingers to a role.  Extra fingers on an already-busy half are ignored.
+	for touch in touches.iter_just_pressed() {
+		if touch.position().x < half_width {
+			if controls.stick.is_none() {
+				controls.stick = Some(VirtualStick {
+					touch_id: touch.id(),
+					origin: touch.position(),
+					position: touch.position(),
+				});
+			}
+		} else if controls.cast_touch.is_none() {
+			controls.cast_touch = Some((touch.id(), touch.position()));
+		}
+	}
+
+	// Follow the fingers we're tracking.  They're allowed to wander across the middle of the screen.
+	if let Some(stick) = controls.stick.as_mut() {
+		if let Some(touch) = touches.get_pressed(stick.touch_id) {
+			stick.position = touch.position();
+		}
+	}
+	if let Some((cast_id, cast_position)) = controls.cast_touch.as_mut() {
+		if let Some(touch) = touches.get_pressed(*cast_id) {
+			*cast_position = touch.position();
 		}
+	}
+}
+
+fn listen_for_rebind(
+	mut pending: ResMut<PendingRebind>,
+	mut bindings: ResMut<InputBindings>,
+	mut user_bindings: ResMut<UserBindings>,
+	keyboard: Res<Input<KeyCode>>,
+	mouse: Res<Input<MouseButton>>,
+	gamepad_buttons: Res<Input<GamepadButton>>,
+) {
+	let action = match pending.0 {
+		Some(action) => action,
+		None => return,
+	};
 
-		let right_trigger = button_axes
-			.get(GamepadButton(gamepad, GamepadButtonType::RightTrigger2))
-			.unwrap();
-		if right_trigger.abs() > 0.01 {
-			info!("{:?} RightTrigger2 value is {}", gamepad, right_trigger);
+	let pressed = if let Some(key) = keyboard.get_just_pressed().next() {
+		Some(InputBinding::Key(*key))
+	} else if let Some(button) = mouse.get_just_pressed().next() {
+		Some(InputBinding::Mouse(*button))
+	} else if let Some(GamepadButton(_, button_type)) = gamepad_buttons.get_just_pressed().next() {
+		Some(InputBinding::Gamepad(*button_type))
+	} else {
+		None
+	};
+
+	if let Some(binding) = pressed {
+		info!("Bound {:?} to {:?}", binding, action);
+		bindings.rebind(action, binding);
+		pending.0 = None;
+		user_bindings.0 = true;
+		let path = user_bindings_path();
+		if let Err(e) = bindings.save(&path) {
+			warn!("Could not save input bindings to {:?}: {}", path, e);
 		}
+	}
+}
 
-		let left_stick_x = axes
-			.get(GamepadAxis(gamepad, GamepadAxisType::LeftStickX))
-			.unwrap();
-		if left_stick_x.abs() > 0.01 {
-			info!("{:?} LeftStickX value is {}", gamepad, left_stick_x);
+// Stand-in for an options screen.  F1-F7 rebind the actions in the order they're declared; the next button pressed is the new binding.
+#[cfg(debug_assertions)]
+fn debug_start_rebind(
+	keyboard: Res<Input<KeyCode>>,
+	mut pending: ResMut<PendingRebind>,
+) {
+	let keys = [
+		(KeyCode::F1, Action::MoveUp),
+		(KeyCode::F2, Action::MoveDown),
+		(KeyCode::F3, Action::MoveLeft),
+		(KeyCode::F4, Action::MoveRight),
+		(KeyCode::F5, Action::CastPrimary),
+		(KeyCode::F6, Action::CastSecondary),
+		(KeyCode::F7, Action::Pause),
+	];
+	for (key, action) in keys {
+		if keyboard.just_pressed(key) {
+			info!("Press something to bind to {:?}.", action);
+			pending.0 = Some(action);
 		}
 	}
 }
 
-// Not using KB yet.
-pub fn keyboard_system(input: Res<Input<KeyCode>>) {
-	if input.any_pressed([KeyCode::LShift, KeyCode::RShift]) {
-		println!("one or both of the two shift keys are pressed");
+fn update_cursor_world_position(
+	windows: Res<Windows>,
+	actions: Res<ActionState>,
+	mut cursor_world: ResMut<CursorWorldPosition>,
+	camera_query: Query<(&Transform, &OrthographicProjection, With<GameplayCamera>)>,
+) {
+	let window = match windows.get_primary() {
+		Some(window) => window,
+		None => return,
+	};
+
+	cursor_world.0 = match (actions.aim, camera_query.iter().next()) {
+		(Aim::Cursor(screen_position), Some((camera_transform, projection, _))) => Some(screen_to_world(screen_position, window, camera_transform, projection)),
+		_ => None,
+	};
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use bevy::input::touch::{TouchInput, TouchPhase};
+	use bevy::window::WindowId;
+
+	const WINDOW_WIDTH: f32 = 800.0;
+	const WINDOW_HEIGHT: f32 = 600.0;
+
+	// Just the touch and action systems, with bevy's input plugin underneath to turn TouchInput events into Touches.
+	fn touch_app() -> App {
+		let mut app = App::new();
+		app.add_plugin(bevy::input::InputPlugin);
+		let mut windows = Windows::default();
+		windows.add(Window::new(
+			WindowId::primary(),
+			&WindowDescriptor { width: WINDOW_WIDTH, height: WINDOW_HEIGHT, ..Default::default() },
+			WINDOW_WIDTH as u32,
+			WINDOW_HEIGHT as u32,
+			1.0,
+			None,
+		));
+		app.insert_resource(windows);
+		app.insert_resource(InputBindings::default());
+		app.insert_resource(ActionState::default());
+		app.insert_resource(TouchControls::default());
+		app.add_system_to_stage(CoreStage::PreUpdate, update_touch_controls.after(InputSystem).before(ActionSystem));
+		app.add_system_to_stage(CoreStage::PreUpdate, update_action_state.label(ActionSystem).after(InputSystem));
+		app
+	}
+
+	fn touch(app: &mut App, id: u64, phase: TouchPhase, x: f32, y: f32) {
+		app.world.get_resource_mut::<Events<TouchInput>>().unwrap().send(TouchInput {
+			phase,
+			position: Vec2::new(x, y),
+			force: None,
+			id,
+		});
+	}
+
+	#[test]
+	fn ignored_action_needs_a_fresh_press() {
+		let mut actions = ActionState::default();
+		actions.set_pressed(Action::CastPrimary, true);
+		actions.ignore_until_released(Action::CastPrimary);
+		assert!(!actions.pressed(Action::CastPrimary));
+		actions.set_pressed(Action::CastPrimary, false);
+		actions.set_pressed(Action::CastPrimary, true);
+		assert!(actions.pressed(Action::CastPrimary));
+		// Not held, so there's nothing to ignore.
+		actions.set_pressed(Action::CastSecondary, false);
+		actions.ignore_until_released(Action::CastSecondary);
+		actions.set_pressed(Action::CastSecondary, true);
+		assert!(actions.pressed(Action::CastSecondary));
+	}
+
+	#[test]
+	fn touch_stick_and_cast() {
+		let mut app = touch_app();
+		let stick_finger = 1;
+		let cast_finger = 2;
+
+		// One finger down on each half.
+		touch(&mut app, stick_finger, TouchPhase::Started, 100.0, 300.0);
+		touch(&mut app, cast_finger, TouchPhase::Started, 600.0, 200.0);
+		app.update();
+		{
+			let controls = app.world.get_resource::<TouchControls>().unwrap();
+			let stick = controls.stick.expect("left half should start the stick");
+			assert_eq!(stick.touch_id, stick_finger);
+			assert_eq!(stick.origin, Vec2::new(100.0, 300.0));
+			assert_eq!(controls.cast_touch, Some((cast_finger, Vec2::new(600.0, 200.0))));
+			let actions = app.world.get_resource::<ActionState>().unwrap();
+			assert!(actions.just_pressed(Action::CastPrimary));
+			assert!(actions.pressed(Action::CastPrimary));
+			assert_eq!(actions.move_axis, Vec2::ZERO); // Hasn't moved off the origin yet.
+		}
+
+		// Drag the stick half a radius right, then way past the radius up, which should clamp to full speed.
+		let radius = app.world.get_resource::<TouchControls>().unwrap().stick_radius;
+		touch(&mut app, stick_finger, TouchPhase::Moved, 100.0 + radius / 2.0, 300.0);
+		app.update();
+		{
+			let controls = app.world.get_resource::<TouchControls>().unwrap();
+			assert_eq!(controls.stick.unwrap().position, Vec2::new(100.0 + radius / 2.0, 300.0));
+			let actions = app.world.get_resource::<ActionState>().unwrap();
+			assert!((actions.move_axis - Vec2::new(0.5, 0.0)).length() < 1e-4);
+			assert!(actions.pressed(Action::CastPrimary));
+			assert!(!actions.just_pressed(Action::CastPrimary));
+		}
+		touch(&mut app, stick_finger, TouchPhase::Moved, 100.0, 300.0 + radius * 3.0);
+		app.update();
+		{
+			let actions = app.world.get_resource::<ActionState>().unwrap();
+			assert!((actions.move_axis.length() - 1.0).abs() < 1e-4);
+		}
+
+		// Lift both.
+		touch(&mut app, stick_finger, TouchPhase::Ended, 100.0, 300.0 + radius * 3.0);
+		touch(&mut app, cast_finger, TouchPhase::Ended, 600.0, 200.0);
+		app.update();
+		{
+			let controls = app.world.get_resource::<TouchControls>().unwrap();
+			assert!(controls.stick.is_none());
+			assert!(controls.cast_touch.is_none());
+			let actions = app.world.get_resource::<ActionState>().unwrap();
+			assert!(actions.just_released(Action::CastPrimary));
+			assert!(!actions.pressed(Action::CastPrimary));
+			assert_eq!(actions.move_axis, Vec2::ZERO);
+		}
 	}
 }
@@ -1,18 +1,16 @@
-use bevy::core::FixedTimestep;
 use bevy::prelude::*;
 use rand::{Rng, thread_rng};
 use crate::{BACKGROUND_RENDER_PRIORITY, SpriteSheets};
+use crate::game_state::AppState;
 
 pub struct LevelPlugin;
 
 impl Plugin for LevelPlugin {
 	fn build(&self, app: &mut App) {
 		app.add_startup_system(initialize_level_plugin);
-		app.add_system_set(
-			SystemSet::new()
-				.with_run_criteria(FixedTimestep::step(1.0))
-				.with_system(regenerate_level)
-		);
+		app.add_system_set(SystemSet::on_enter(AppState::Playing).with_system(request_new_level));
+		app.add_system_set(SystemSet::on_update(AppState::Playing).with_system(regenerate_level));
+		app.add_system_set(SystemSet::on_exit(AppState::Playing).with_system(despawn_level));
 	}
 }
 
@@ -40,6 +38,22 @@ fn initialize_level_plugin(
 	})
 }
 
+// Fresh floor every run.
+fn request_new_level(
+	mut level: ResMut<Level>,
+) {
+	level.needs_regeneration = true;
+}
+
+fn despawn_level(
+	mut commands: Commands,
+	tiles: Query<(Entity, With<Tile>)>,
+) {
+	for (entity, _) in tiles.iter() {
+		commands.entity(entity).despawn();
+	}
+}
+
 fn regenerate_level(
 	mut commands: Commands,
 	mut level: ResMut<Level>,
@@ -1,22 +1,38 @@
+mod animation;
+mod boss;
+mod camera;
+mod collision;
+mod damage;
+mod effects;
 mod enemy;
+mod enemy_ai;
+mod enemy_archetype;
+mod feedback;
+mod game_state;
+mod hud;
 mod input;
 mod level;
+mod particles;
 mod player;
+mod projectile;
+mod score;
 mod spells;
 mod ui_text;
+mod waves;
 
+use bevy::asset::{AssetPath, LoadContext, LoadedAsset};
 use bevy::prelude::*;
 use enemy::*;
-use input::{input_event_system, touch_system, mouse_click_system};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::time::Duration;
 use bevy::render::view::VisibleEntities;
-use rand::{Rand, Rng, thread_rng};
 
 const WINDOW_SCALE:f32 = 1.0/2.0;
 const BACKGROUND_RENDER_PRIORITY:f32 = 0.0;
 const PLAYER_RENDER_PRIORITY:f32 = 1.0; // Higher = on top.
 const ENEMY_RENDER_PRIORITY:f32 = 1.1; // Slightly higher than player.
-const CAMERA_SHAKE_LERP_FACTOR:f32 = 0.1;
 
 // Maybe add https://github.com/Trouv/bevy_ecs_ldtk
 // https://github.com/PhaestusFox/bevy_sprite_animation
@@ -26,17 +42,7 @@ struct SpriteSheets {
 	//enemy_material: Handle<ColorMaterial>,
 	level_tileset: Handle<TextureAtlas>,
 	player_material: Handle<TextureAtlas>,
-	enemy_material: Handle<TextureAtlas>,
 	explosion: Handle<TextureAtlas>,
-	magic_missile: Handle<TextureAtlas>,
-}
-
-#[derive(Default)]
-struct ScreenShake {
-	magnitude: f32,
-	decay: f32,
-	target_offset: Vec2,
-	target_rotation: f32,
 }
 
 struct WindowBounds {
@@ -50,10 +56,75 @@ struct WindowBounds {
 }
 // END Resources
 
+// Shared by the RON asset loaders (spells, enemies) to describe a grid sprite sheet.
+#[derive(Clone, Debug, Deserialize)]
+struct SpriteDefinition {
+	path: String,
+	tile_size: (f32, f32),
+	columns: usize,
+	rows: usize,
+	frame_time: f32,
+	#[serde(default = "default_sprite_scale")]
+	scale: f32,
+	#[serde(default)]
+	animations: HashMap<animation::AnimationState, animation::AnimationClip>,
+}
+
+fn default_sprite_scale() -> f32 {
+	1.0
+}
+
+impl SpriteDefinition {
+	// Adds the atlas as a labeled sub-asset of whatever is being loaded.
+	// The returned path is the image, which the caller needs to list as a dependency.
+	fn load_atlas(&self, load_context: &mut LoadContext, label: &str) -> (Handle<TextureAtlas>, AssetPath<'static>) {
+		let texture_path = AssetPath::new(self.path.clone().into(), None);
+		let texture: Handle<Image> = load_context.get_handle(texture_path.clone());
+		let atlas = TextureAtlas::from_grid(
+			texture,
+			Vec2::new(self.tile_size.0, self.tile_size.1),
+			self.columns,
+			self.rows
+		);
+		(load_context.set_labeled_asset(label, LoadedAsset::new(atlas)), texture_path)
+	}
+
+	// What goes in the AnimationLibrary for this sheet.  No animations listed means loop the whole sheet at frame_time.
+	fn clips(&self) -> HashMap<animation::AnimationState, animation::AnimationClip> {
+		if !self.animations.is_empty() {
+			return self.animations.clone();
+		}
+		let mut clips = HashMap::new();
+		clips.insert(animation::AnimationState::Idle, animation::AnimationClip {
+			first: 0,
+			last: (self.columns * self.rows).max(1) - 1,
+			fps: 1.0 / self.frame_time.max(1e-3),
+			mode: animation::PlaybackMode::Loop,
+		});
+		clips
+	}
+}
+
+// Anything we write for the player (high scores, rebinds) goes here, never into assets/.
+// $XDG_DATA_HOME/heckin_wizard (or ~/.local/share/heckin_wizard), %APPDATA%\heckin_wizard on Windows.
+// Falls back to the working directory if none of those are set.
+fn user_data_path(file: &str) -> PathBuf {
+	let base = std::env::var_os("XDG_DATA_HOME").map(PathBuf::from)
+		.or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))
+		.or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("share")));
+	match base {
+		Some(dir) => dir.join("heckin_wizard").join(file),
+		None => PathBuf::from(file),
+	}
+}
+
 // Components:
 #[derive(Component)]
 struct DestroyOnOOB; // If assigned to an entity, will get deleted when it moves off camera.
 
+#[derive(Component)]
+struct Lifetime(Timer); // Despawned when the (non-repeating) timer finishes.
+
 #[derive(Component)]
 struct Health(f32);
 
@@ -81,22 +152,35 @@ fn main() {
 		.add_startup_system(setup)
 
 		// Technically startup systems, but should happen after startup.
+		.add_plugin(game_state::GameStatePlugin)
+		.add_plugin(input::InputPlugin)
+		.add_plugin(collision::CollisionPlugin)
+		.add_plugin(damage::DamagePlugin)
+		.add_plugin(feedback::FeedbackPlugin)
+		.add_plugin(animation::AnimationPlugin)
+		.add_plugin(particles::ParticlePlugin)
 		.add_plugin(ui_text::TextDisplayPlugin)
+		.add_plugin(camera::CameraPlugin)
 		.add_plugin(level::LevelPlugin)
 		.add_plugin(player::PlayerPlugin)
 		.add_plugin(enemy::EnemyPlugin)
+		.add_plugin(enemy_ai::EnemyAiPlugin)
+		.add_plugin(boss::BossPlugin)
+		.add_plugin(projectile::ProjectilePlugin)
 		.add_plugin(spells::SpellPlugin)
+		.add_plugin(score::ScorePlugin)
+		.add_plugin(hud::HudPlugin)
 
 		// Rendering
-		.add_system(animate_sprite_system)
 		.add_system(clean_oob_components)
-		.add_system(apply_screen_shake)
-		// Movement
-		.add_system(movement)
-		// Inputs:
-		.add_system(input_event_system)
-		.add_system(touch_system)
-		.add_system(mouse_click_system)
+		// Frozen while paused.
+		.add_system_set(
+			SystemSet::on_update(game_state::AppState::Playing)
+				.with_system(expire_lifetimes)
+				// Movement
+				.with_system(movement)
+		)
+		.add_system_set(SystemSet::on_exit(game_state::AppState::Playing).with_system(despawn_effects))
 		// Gameplay
 		// Yeet
 		.run();
@@ -127,12 +211,6 @@ fn setup(
 	//camera.camera.far = 10.0;
 	commands.spawn_bundle(camera).insert(GameplayCamera);
 	commands.spawn_bundle(UiCameraBundle::default());
-	commands.insert_resource(ScreenShake {
-		magnitude: 0.0,
-		decay: 1.5,  // Larger -> Faster return to static.
-		target_offset: Vec2::new(0.0,0.0),
-		target_rotation: 0.0
-	});
 
 	// Need some RNG?
 	// use thread_rng instead of commands.insert_resource(rand::StdRng::new().unwrap());
@@ -142,87 +220,23 @@ fn setup(
 
 	let player_texture_atlas_handle = atlas_assets.add(TextureAtlas::from_grid(asset_server.load("player_1x10.png"), Vec2::new(16.0, 16.0), 10, 1));
 
-	let enemy_texture_handle = asset_server.load("enemy_1x4.png");
-	let enemy_texture_atlas = TextureAtlas::from_grid(enemy_texture_handle, Vec2::new(16.0, 16.0), 4, 1);
-	let enemy_texture_atlas_handle = atlas_assets.add(enemy_texture_atlas);
-
 	let explosion_texture_atlas_handle = atlas_assets.add(TextureAtlas::from_grid(asset_server.load("explosion_1x6.png"), Vec2::new(16.0, 16.0), 6, 1));
 
-	let magic_missile_texture_atlas_handle = atlas_assets.add(TextureAtlas::from_grid(asset_server.load("magic_missile_head.png"), Vec2::new(16.0, 16.0), 3, 1));
-
 	commands.insert_resource(SpriteSheets {
 		level_tileset: level_tileset_handle,
 		player_material: player_texture_atlas_handle,
-		enemy_material: enemy_texture_atlas_handle,
 		explosion: explosion_texture_atlas_handle,
-		magic_missile: magic_missile_texture_atlas_handle,
 	});
-
-	commands.spawn().insert(ui_text::UIText::from_string("You're a Heckin' Wizard!  Fight!".to_string()));
-}
-
-fn apply_screen_shake(
-	mut screen_shake: ResMut<ScreenShake>,
-	mut camera_query: Query<(&mut Transform, With<GameplayCamera>)>,
-) {
-	// Stupid shit hacky camera shake.
-	/*
-	Rather than use the right thing: real perlin noise or the profoundly stupid thing: randint,
-	we're going to split the difference and pick a random point, LERP to it, and select a new random point as a function of the distance.
-	If we're on top of the point, our likelihood of keeping the point d(cam, target) is 0.
-	If we're a long ways away, our likelihood of keeping the target is 1.0/(1.0+x), which goes to 1.
-	The new random point is selected at a distance of log(shake_intensity), which could be problematic because we have no harmonics to make fine jitters.
-	*/
-	let (mut camera_transform, _) = camera_query.single_mut();
-	// If there is no screen shake active, return early.
-	if screen_shake.magnitude < 1e-6 {
-		screen_shake.magnitude = 0.0;
-		return;
-	}
-
-	// Camera is Vec3.  Our target is Vec2.  We don't want to mess with the depth of the orthographic camera because everything could get thrown off and now show up.
-	// For efficiency, pull out DX/DY.
-	let camera_dx = screen_shake.target_offset.x - camera_transform.translation.x;
-	let camera_dy = screen_shake.target_offset.y - camera_transform.translation.y;
-	// Move closer to new point.  Lazily split x/y rather than cast to Vec2.
-	camera_transform.translation.x = camera_transform.translation.x + (camera_dx*(1.0-CAMERA_SHAKE_LERP_FACTOR));
-	camera_transform.translation.y = camera_transform.translation.y + (camera_dy*(1.0-CAMERA_SHAKE_LERP_FACTOR));
-	// Now maybe move to a new place.
-	let distance_squared = camera_dx*camera_dx + camera_dy*camera_dy;
-	let keep_target_probability = distance_squared / (1.0 + distance_squared);
-	let mut rng = thread_rng();
-	if rng.next_f32() > keep_target_probability {
-		// Need a new target.
-		let log_intensity = screen_shake.magnitude.log2().max(0.0);
-		let new_x = 2.0*(rng.next_f32()-0.5) * log_intensity;
-		let new_y = 2.0*(rng.next_f32()-0.5) * log_intensity;
-		screen_shake.target_offset = Vec2::new(new_x, new_y);
-	}
-	// Decay
-	screen_shake.magnitude /= screen_shake.decay;
-}
-
-fn animate_sprite_system(
-	time: Res<Time>,
-	texture_atlases: Res<Assets<TextureAtlas>>,
-	mut query: Query<(&mut Timer, &mut TextureAtlasSprite, &Handle<TextureAtlas>)>,
-) {
-	for (mut timer, mut sprite, texture_atlas_handle) in query.iter_mut() {
-		timer.tick(time.delta());
-		if timer.finished() {
-			let texture_atlas = texture_atlases.get(texture_atlas_handle).unwrap();
-			sprite.index = (sprite.index + 1) % texture_atlas.textures.len();
-		}
-	}
 }
 
 fn movement(
 	time: Res<Time>,
+	hit_stop: Res<feedback::HitStop>,
 	mut query: Query<(&mut Transform, &Velocity)>
 ) {
-	let dt = time.delta();
+	let dt = hit_stop.delta_seconds(&time);
 	for (mut tf, velocity) in query.iter_mut() {
-		tf.translation += velocity.0 * dt.as_secs_f32();
+		tf.translation += velocity.0 * dt;
 	}
 }
 
@@ -240,6 +254,30 @@ fn clean_oob_components(
 	}
 }
 
+fn expire_lifetimes(
+	mut commands: Commands,
+	time: Res<Time>,
+	hit_stop: Res<feedback::HitStop>,
+	mut query: Query<(Entity, &mut Lifetime)>,
+) {
+	for (entity, mut lifetime) in query.iter_mut() {
+		lifetime.0.tick(hit_stop.delta(&time));
+		if lifetime.0.finished() {
+			commands.entity(entity).despawn();
+		}
+	}
+}
+
+// Explosions, damage numbers and anything else on a timer.  Leftovers from the last run.  Projectiles clean up after themselves.
+fn despawn_effects(
+	mut commands: Commands,
+	query: Query<Entity, (Or<(With<Lifetime>, With<effects::Effect>)>, Without<projectile::Faction>)>,
+) {
+	for entity in query.iter() {
+		commands.entity(entity).despawn();
+	}
+}
+
 //struct GreetTimer(Timer);
 //app.insert_resource(GreetTimer(Timer::from_seconds(2.0, true)))  // True means repeat.
 //fn greet_enemies(time: Res<Time>, mut timer: ResMut<GreetTimer>, query: Query<&Transform, With<Enemy>>) {
@@ -0,0 +1,341 @@
+use bevy::prelude::*;
+use rand::{Rng, ThreadRng, thread_rng};
+use serde::Deserialize;
+
+use crate::{SpriteSheets, Velocity, ENEMY_RENDER_PRIORITY};
+use crate::damage::DamageEvent;
+use crate::enemy::{EnemyKilled, WaveCleared};
+use crate::feedback::HitStop;
+use crate::game_state::AppState;
+use crate::player::{Player, PlayerDied};
+
+const POOL_SIZE: usize = 1024; // Hard cap.  Past this, new particles just don't show up.
+const PARTICLE_RENDER_PRIORITY: f32 = ENEMY_RENDER_PRIORITY + 0.05; // Over the actors, under explosions.
+
+// CPU particles drawn off the explosion sheet.  Every particle is a sprite from a fixed pool spawned up front,
+// so a big fight is just flipping visibility and moving things around rather than spawning and despawning hundreds of entities.
+// Projectiles carry a ParticleEmitter for trails.  Bursts for hits, deaths and wave clears come straight off the gameplay events.
+pub struct ParticlePlugin;
+
+impl Plugin for ParticlePlugin {
+	fn build(&self, app: &mut App) {
+		app.insert_resource(ParticlePresets::default());
+		app.insert_resource(ParticlePool::default());
+		// SpriteSheets is inserted by a startup system, so wait for it.
+		app.add_startup_system_to_stage(StartupStage::PostStartup, spawn_particle_pool);
+		app.add_system_set(
+			SystemSet::on_update(AppState::Playing)
+				.with_system(run_emitters.label(ParticleSystem::Emit))
+				.with_system(burst_on_events.label(ParticleSystem::Emit))
+				.with_system(update_particles.after(ParticleSystem::Emit))
+		);
+		app.add_system_set(SystemSet::on_exit(AppState::Playing).with_system(clear_particles));
+	}
+}
+
+#[derive(SystemLabel, Clone, Hash, Debug, PartialEq, Eq)]
+enum ParticleSystem {
+	Emit,
+}
+
+// What a batch of particles looks like and how it moves.  Spells list their trails in RON, so this deserializes.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ParticleSpec {
+	#[serde(default)]
+	pub rate: f32, // Per second, for emitters.  Bursts ignore it.
+	#[serde(default)]
+	pub burst: u32, // How many at once.  Emitters fire this many when they first show up.
+	pub lifetime: f32, // Seconds.
+	pub speed: (f32, f32), // Picked at random between the two.
+	#[serde(default = "full_circle")]
+	pub cone: f32, // Degrees, centred on the emit direction.  360 = every which way.
+	#[serde(default)]
+	pub gravity: (f32, f32),
+	pub start_color: (f32, f32, f32, f32),
+	pub end_color: (f32, f32, f32, f32),
+	pub start_size: f32, // Scale of the 16x16 sprite.
+	pub end_size: f32,
+	#[serde(default)]
+	pub frames: (usize, usize), // Explosion sheet frames to play through over the particle's life.
+}
+
+fn full_circle() -> f32 {
+	360.0
+}
+
+impl ParticleSpec {
+	pub fn with_color(mut self, color: Color) -> Self {
+		self.start_color = (color.r(), color.g(), color.b(), self.start_color.3);
+		self.end_color = (color.r(), color.g(), color.b(), self.end_color.3);
+		self
+	}
+}
+
+// Resources:
+pub struct ParticlePresets {
+	pub impact: ParticleSpec, // Tinted by element.
+	pub death: ParticleSpec,
+	pub wave_clear: ParticleSpec,
+}
+
+impl Default for ParticlePresets {
+	fn default() -> Self {
+		ParticlePresets {
+			impact: ParticleSpec {
+				rate: 0.0,
+				burst: 6,
+				lifetime: 0.25,
+				speed: (20.0, 60.0),
+				cone: 360.0,
+				gravity: (0.0, 0.0),
+				start_color: (1.0, 1.0, 1.0, 1.0),
+				end_color: (1.0, 1.0, 1.0, 0.0),
+				start_size: 0.3,
+				end_size: 0.1,
+				frames: (0, 1),
+			},
+			death: ParticleSpec {
+				rate: 0.0,
+				burst: 20,
+				lifetime: 0.6,
+				speed: (15.0, 70.0),
+				cone: 360.0,
+				gravity: (0.0, -60.0),
+				start_color: (1.0, 0.8, 0.3, 1.0),
+				end_color: (0.6, 0.1, 0.1, 0.0),
+				start_size: 0.5,
+				end_size: 0.15,
+				frames: (0, 5),
+			},
+			wave_clear: ParticleSpec {
+				rate: 0.0,
+				burst: 40,
+				lifetime: 1.0,
+				speed: (40.0, 50.0), // Narrow, so it goes out as a ring.
+				cone: 360.0,
+				gravity: (0.0, 20.0),
+				start_color: (0.7, 0.9, 1.0, 1.0),
+				end_color: (0.8, 0.5, 1.0, 0.0),
+				start_size: 0.4,
+				end_size: 0.2,
+				frames: (0, 2),
+			},
+		}
+	}
+}
+
+#[derive(Default)]
+struct ParticlePool {
+	free: Vec<Entity>,
+}
+
+// Components:
+// Spits out particles while it exists.  Trails point away from the way the entity is going.
+#[derive(Component)]
+pub struct ParticleEmitter {
+	spec: ParticleSpec,
+	owed: f32, // Fractional particles carried over between frames.
+	burst_done: bool,
+}
+
+impl ParticleEmitter {
+	pub fn new(spec: ParticleSpec) -> Self {
+		ParticleEmitter {
+			spec,
+			owed: 0.0,
+			burst_done: false,
+		}
+	}
+}
+
+#[derive(Component, Default)]
+struct Particle {
+	active: bool,
+	age: f32,
+	lifetime: f32,
+	velocity: Vec2,
+	gravity: Vec2,
+	start_color: Color,
+	end_color: Color,
+	start_size: f32,
+	end_size: f32,
+	frames: (usize, usize),
+}
+
+type ParticleQuery<'w, 's> = Query<'w, 's, (&'static mut Particle, &'static mut Transform, &'static mut TextureAtlasSprite, &'static mut Visibility)>;
+
+// Systems:
+fn spawn_particle_pool(
+	mut commands: Commands,
+	sprite_sheets: Res<SpriteSheets>,
+	mut pool: ResMut<ParticlePool>,
+) {
+	for _ in 0..POOL_SIZE {
+		let entity = commands
+			.spawn_bundle(SpriteSheetBundle {
+				texture_atlas: sprite_sheets.explosion.clone(),
+				visibility: Visibility { is_visible: false },
+				..Default::default()
+			})
+			.insert(Particle::default())
+			.id();
+		pool.free.push(entity);
+	}
+}
+
+// Hands `count` particles out of the pool.  If it runs dry the rest are dropped.
+fn emit(
+	pool: &mut ParticlePool,
+	particles: &mut ParticleQuery,
+	rng: &mut ThreadRng,
+	spec: &ParticleSpec,
+	origin: Vec2,
+	direction: Vec2,
+	count: u32,
+) {
+	let base_angle = direction.y.atan2(direction.x);
+	let half_cone = spec.cone.to_radians() / 2.0;
+	for _ in 0..count {
+		let entity = match pool.free.pop() {
+			Some(entity) => entity,
+			None => return,
+		};
+		let (mut particle, mut transform, mut sprite, mut visibility) = match particles.get_mut(entity) {
+			Ok(p) => p,
+			Err(_) => continue, // Shouldn't happen, but don't put it back if it's gone.
+		};
+
+		let angle = base_angle + if half_cone > 0.0 { rng.gen_range(-half_cone, half_cone) } else { 0.0 };
+		let speed = if spec.speed.1 > spec.speed.0 { rng.gen_range(spec.speed.0, spec.speed.1) } else { spec.speed.0 };
+		*particle = Particle {
+			active: true,
+			age: 0.0,
+			lifetime: spec.lifetime.max(1e-3),
+			velocity: Vec2::new(angle.cos(), angle.sin()) * speed,
+			gravity: Vec2::new(spec.gravity.0, spec.gravity.1),
+			start_color: Color::rgba(spec.start_color.0, spec.start_color.1, spec.start_color.2, spec.start_color.3),
+			end_color: Color::rgba(spec.end_color.0, spec.end_color.1, spec.end_color.2, spec.end_color.3),
+			start_size: spec.start_size,
+			end_size: spec.end_size,
+			frames: (spec.frames.0, spec.frames.1.max(spec.frames.0)),
+		};
+		transform.translation = origin.extend(PARTICLE_RENDER_PRIORITY);
+		transform.scale = Vec3::new(spec.start_size, spec.start_size, 1.0);
+		sprite.index = spec.frames.0;
+		sprite.color = particle.start_color;
+		visibility.is_visible = true;
+	}
+}
+
+fn run_emitters(
+	time: Res<Time>,
+	hit_stop: Res<HitStop>,
+	mut pool: ResMut<ParticlePool>,
+	mut emitters: Query<(&Transform, Option<&Velocity>, &mut ParticleEmitter), Without<Particle>>,
+	mut particles: ParticleQuery,
+) {
+	let dt = hit_stop.delta_seconds(&time);
+	let mut rng = thread_rng();
+	for (transform, velocity, mut emitter) in emitters.iter_mut() {
+		let direction = match velocity {
+			Some(velocity) if velocity.0.truncate().length_squared() > 0.0 => -velocity.0.truncate().normalize(),
+			_ => Vec2::Y,
+		};
+		let mut count = 0;
+		if !emitter.burst_done {
+			emitter.burst_done = true;
+			count += emitter.spec.burst;
+		}
+		emitter.owed += emitter.spec.rate * dt;
+		let whole = emitter.owed.floor();
+		emitter.owed -= whole;
+		count += whole as u32;
+		if count > 0 {
+			emit(&mut pool, &mut particles, &mut rng, &emitter.spec, transform.translation.truncate(), direction, count);
+		}
+	}
+}
+
+fn burst_on_events(
+	presets: Res<ParticlePresets>,
+	mut pool: ResMut<ParticlePool>,
+	mut damage_events: EventReader<DamageEvent>,
+	mut killed_events: EventReader<EnemyKilled>,
+	mut died_events: EventReader<PlayerDied>,
+	mut wave_cleared_events: EventReader<WaveCleared>,
+	player: Query<&Transform, (With<Player>, Without<Particle>)>,
+	mut particles: ParticleQuery,
+) {
+	let mut rng = thread_rng();
+	for event in damage_events.iter() {
+		let spec = presets.impact.clone().with_color(event.element.color());
+		emit(&mut pool, &mut particles, &mut rng, &spec, event.position, Vec2::Y, spec.burst);
+	}
+	for event in killed_events.iter() {
+		emit(&mut pool, &mut particles, &mut rng, &presets.death, event.position, Vec2::Y, presets.death.burst);
+	}
+	for event in died_events.iter() {
+		// Losing a life gets double.
+		emit(&mut pool, &mut particles, &mut rng, &presets.death, event.position, Vec2::Y, presets.death.burst * 2);
+	}
+	if wave_cleared_events.iter().next().is_some() {
+		if let Some(transform) = player.iter().next() {
+			emit(&mut pool, &mut particles, &mut rng, &presets.wave_clear, transform.translation.truncate(), Vec2::Y, presets.wave_clear.burst);
+		}
+	}
+}
+
+fn update_particles(
+	time: Res<Time>,
+	hit_stop: Res<HitStop>,
+	mut pool: ResMut<ParticlePool>,
+	mut particles: Query<(Entity, &mut Particle, &mut Transform, &mut TextureAtlasSprite, &mut Visibility)>,
+) {
+	let dt = hit_stop.delta_seconds(&time);
+	for (entity, mut particle, mut transform, mut sprite, mut visibility) in particles.iter_mut() {
+		if !particle.active {
+			continue;
+		}
+		particle.age += dt;
+		if particle.age >= particle.lifetime {
+			particle.active = false;
+			visibility.is_visible = false;
+			pool.free.push(entity);
+			continue;
+		}
+
+		let gravity = particle.gravity;
+		particle.velocity += gravity * dt;
+		transform.translation += (particle.velocity * dt).extend(0.0);
+
+		let t = particle.age / particle.lifetime;
+		let size = particle.start_size + (particle.end_size - particle.start_size) * t;
+		transform.scale = Vec3::new(size, size, 1.0);
+		sprite.color = lerp_color(particle.start_color, particle.end_color, t);
+		let (first, last) = particle.frames;
+		sprite.index = first + (((last - first + 1) as f32 * t) as usize).min(last - first);
+	}
+}
+
+fn lerp_color(from: Color, to: Color, t: f32) -> Color {
+	Color::rgba(
+		from.r() + (to.r() - from.r()) * t,
+		from.g() + (to.g() - from.g()) * t,
+		from.b() + (to.b() - from.b()) * t,
+		from.a() + (to.a() - from.a()) * t,
+	)
+}
+
+// Back in the pool for next time.
+fn clear_particles(
+	mut pool: ResMut<ParticlePool>,
+	mut particles: Query<(Entity, &mut Particle, &mut Visibility)>,
+) {
+	for (entity, mut particle, mut visibility) in particles.iter_mut() {
+		if particle.active {
+			particle.active = false;
+			visibility.is_visible = false;
+			pool.free.push(entity);
+		}
+	}
+}
@@ -1,37 +1,197 @@
 use std::borrow::Borrow;
-use bevy::core::FixedTimestep;
 use bevy::prelude::*;
 use rand::{Rng, thread_rng};
-use crate::{Health, PLAYER_RENDER_PRIORITY, SpriteSheets, Velocity};
+use crate::{Health, PLAYER_RENDER_PRIORITY, SpriteSheets, Velocity, WindowBounds};
+use crate::boss::Boss;
+use crate::collision::{Collider, ColliderShape, CollisionEvent, LAYER_ENEMY, LAYER_ENEMY_PROJECTILE, LAYER_PICKUP, LAYER_PLAYER};
+use crate::damage::{ContactDamage, DamageEvent, DespawnOnContact, Element};
+use crate::effects::{spawn_death_effect, spawn_impact_effect};
+use crate::enemy::{Enemy, SpawnTelegraph};
+use crate::feedback::HitStop;
+use crate::game_state::AppState;
+use crate::input::ActionState;
+use crate::projectile::Faction;
+use crate::spells::Mana;
+
+pub const PLAYER_HEALTH: f32 = 10.0f32;
+const PLAYER_HALF_SIZE: f32 = 8.0f32; // Half of the 16x16 sprite so we don't clip off the edge of the screen.
+const PLAYER_DEATH_EFFECT_SCALE: f32 = 2.0; // Losing a life should look like a bigger deal than popping a slime.
 
 pub struct PlayerPlugin;
 
 impl Plugin for PlayerPlugin {
 	fn build(&self, app: &mut App) {
 		//app.add_startup_system(player_startup);
+		app.insert_resource(PlayerMovementSettings::default());
+		app.insert_resource(PlayerDamageSettings::default());
+		app.insert_resource(LivesSettings::default());
+		app.insert_resource(Lives(0));
+		app.insert_resource(RespawnTimer(None));
+		app.add_event::<PlayerDamaged>();
+		app.add_event::<PlayerDied>();
+		app.add_event::<RunEnded>();
+		app.add_system_set(SystemSet::on_enter(AppState::Playing).with_system(start_run));
 		app.add_system_set(
-			SystemSet::new()
-				.with_run_criteria(FixedTimestep::step(1.0))
+			SystemSet::on_update(AppState::Playing)
+				.with_system(check_for_player_death)
 				.with_system(respawn_player)
+				.with_system(player_movement_input)
+				.with_system(clamp_player_to_bounds)
+				.with_system(apply_contact_damage)
+				.with_system(tick_invulnerability)
 		);
-		app.add_system(check_for_player_death);
+		app.add_system_set(SystemSet::on_exit(AppState::Playing).with_system(despawn_player));
+	}
+}
+
+// Resources:
+// Tuning knobs for the movement controller.  Change these at runtime to play with the feel.
+pub struct PlayerMovementSettings {
+	pub max_speed: f32,
+	pub acceleration: f32, // Units per second per second while a direction is held.
+	pub friction: f32, // Larger -> Faster stop when nothing is held.
+}
+
+impl Default for PlayerMovementSettings {
+	fn default() -> Self {
+		PlayerMovementSettings {
+			max_speed: 80.0,
+			acceleration: 600.0,
+			friction: 10.0,
+		}
 	}
 }
 
+pub struct PlayerDamageSettings {
+	pub invulnerability_seconds: f32, // After a hit, how long before we can be hit again.
+	pub flash_interval: f32, // Seconds between sprite blinks while invulnerable.
+	pub knockback_speed: f32,
+}
+
+impl Default for PlayerDamageSettings {
+	fn default() -> Self {
+		PlayerDamageSettings {
+			invulnerability_seconds: 1.0,
+			flash_interval: 0.08,
+			knockback_speed: 150.0,
+		}
+	}
+}
+
+pub struct LivesSettings {
+	pub starting_lives: u32,
+	pub hardcore: bool, // One life, whatever starting_lives says.
+	pub respawn_delay: f32, // Seconds between dying and coming back.
+	pub spawn_protection_seconds: f32,
+	pub death_clear_radius: f32, // Enemies and enemy bullets this close to where we died are removed.
+}
+
+impl Default for LivesSettings {
+	fn default() -> Self {
+		LivesSettings {
+			starting_lives: 3,
+			hardcore: false,
+			respawn_delay: 1.5,
+			spawn_protection_seconds: 2.0,
+			death_clear_radius: 80.0,
+		}
+	}
+}
+
+impl LivesSettings {
+	pub fn lives_per_run(&self) -> u32 {
+		if self.hardcore { 1 } else { self.starting_lives.max(1) }
+	}
+}
+
+// Lives left, counting the one in use.
+pub struct Lives(pub u32);
+
+// Counting down to the next life.  Also remembers where we died, which is where we come back.
+pub struct RespawnTimer(pub Option<(Timer, Vec2)>);
+
+// Events:
+pub struct PlayerDied {
+	pub position: Vec2,
+	pub lives_remaining: u32,
+}
+
+// Out of lives.  The run is over.
+pub struct RunEnded;
+
+pub struct PlayerDamaged {
+	pub source: Entity,
+	pub amount: f32,
+}
+
+// Components:
 #[derive(Component)]
 pub struct Player;
 
-fn respawn_player(
+// While present, contact damage is ignored and the sprite blinks.
+#[derive(Component)]
+pub struct Invulnerable {
+	pub timer: Timer,
+	pub flash_timer: Timer,
+}
+
+impl Invulnerable {
+	pub fn new(seconds: f32, flash_interval: f32) -> Self {
+		Invulnerable {
+			timer: Timer::from_seconds(seconds, false),
+			flash_timer: Timer::from_seconds(flash_interval, true),
+		}
+	}
+}
+
+fn start_run(
 	mut commands: Commands,
+	settings: Res<LivesSettings>,
+	mut lives: ResMut<Lives>,
+	mut respawn_timer: ResMut<RespawnTimer>,
 	atlas_assets: Res<Assets<TextureAtlas>>,
 	sprite_sheets: Res<SpriteSheets>,
-	//time: Res<Time>,
 	player_query: Query<With<Player>>,
 ) {
-	if let Some(_) = player_query.iter().next() {
-		return; // Nothing to do.
+	lives.0 = settings.lives_per_run();
+	respawn_timer.0 = None;
+	if player_query.iter().next().is_none() {
+		spawn_player(&mut commands, &atlas_assets, &sprite_sheets, Vec2::ZERO);
 	}
+}
+
+fn respawn_player(
+	mut commands: Commands,
+	time: Res<Time>,
+	settings: Res<LivesSettings>,
+	damage_settings: Res<PlayerDamageSettings>,
+	mut respawn_timer: ResMut<RespawnTimer>,
+	hit_stop: Res<HitStop>,
+	atlas_assets: Res<Assets<TextureAtlas>>,
+	sprite_sheets: Res<SpriteSheets>,
+) {
+	let position = match respawn_timer.0.as_mut() {
+		Some((timer, position)) => {
+			timer.tick(hit_stop.delta(&time));
+			if !timer.finished() {
+				return;
+			}
+			*position
+		},
+		None => return, // Alive, or out of lives.
+	};
+	respawn_timer.0 = None;
 
+	let player = spawn_player(&mut commands, &atlas_assets, &sprite_sheets, position);
+	commands.entity(player).insert(Invulnerable::new(settings.spawn_protection_seconds, damage_settings.flash_interval));
+}
+
+fn spawn_player(
+	commands: &mut Commands,
+	atlas_assets: &Assets<TextureAtlas>,
+	sprite_sheets: &SpriteSheets,
+	position: Vec2,
+) -> Entity {
 	//let player_texture_atlas = atlas_assets.get(&sprite_sheets.player_material).expect("Player texture is not loaded.");
 	//let num_faces = player_texture_atlas.len();
 	let num_faces = 10;
@@ -39,7 +199,7 @@ fn respawn_player(
 	let mut sb = SpriteSheetBundle {
 		texture_atlas: atlas_assets.get_handle(&sprite_sheets.player_material),
 		transform: Transform {
-			translation: Vec3::new(0.0, 0.0, PLAYER_RENDER_PRIORITY),
+			translation: position.extend(PLAYER_RENDER_PRIORITY),
 			..Default::default()
 		},
 		..Default::default()
@@ -52,19 +212,191 @@ fn respawn_player(
 	// Spawn!
 	commands
 		.spawn_bundle(sb)
-		.insert(Health(10.0f32))
-		.insert(Player);
+		.insert(Health(PLAYER_HEALTH))
+		.insert(Velocity(Vec3::ZERO))
+		.insert(Mana::default())
+		.insert(Collider::new(ColliderShape::SpriteRect, LAYER_PLAYER, LAYER_ENEMY | LAYER_ENEMY_PROJECTILE | LAYER_PICKUP))
+		.insert(Player)
+		.id()
+}
+
+fn player_movement_input(
+	time: Res<Time>,
+	settings: Res<PlayerMovementSettings>,
+	hit_stop: Res<HitStop>,
+	actions: Res<ActionState>,
+	mut player_query: Query<(&mut Velocity, With<Player>)>,
+) {
+	let direction = actions.move_axis;
+
+	let dt = hit_stop.delta_seconds(&time);
+	for (mut velocity, _) in player_query.iter_mut() {
+		let current = Vec2::new(velocity.0.x, velocity.0.y);
+		let new_velocity = if direction.length_squared() > 0.0 {
+			// Accelerate toward the target velocity without overshooting it.
+			let delta = direction * settings.max_speed - current;
+			let max_step = settings.acceleration * dt;
+			if delta.length() <= max_step {
+				current + delta
+			} else {
+				current + delta.normalize() * max_step
+			}
+		} else {
+			// Nothing held.  Bleed off speed.  Exponential so it doesn't depend on the frame rate.
+			current * (-settings.friction * dt).exp()
+		};
+		velocity.0.x = new_velocity.x;
+		velocity.0.y = new_velocity.y;
+	}
+}
+
+fn clamp_player_to_bounds(
+	window: Res<WindowBounds>,
+	mut player_query: Query<(&mut Transform, &mut Velocity, With<Player>)>,
+) {
+	for (mut transform, mut velocity, _) in player_query.iter_mut() {
+		let min_x = window.left + PLAYER_HALF_SIZE;
+		let max_x = window.right - PLAYER_HALF_SIZE;
+		let min_y = window.bottom + PLAYER_HALF_SIZE;
+		let max_y = window.top - PLAYER_HALF_SIZE;
+
+		// Kill the velocity into the wall too, otherwise we'd have to accelerate back out of it.
+		if transform.translation.x < min_x || transform.translation.x > max_x {
+			transform.translation.x = transform.translation.x.clamp(min_x, max_x);
+			velocity.0.x = 0.0;
+		}
+		if transform.translation.y < min_y || transform.translation.y > max_y {
+			transform.translation.y = transform.translation.y.clamp(min_y, max_y);
+			velocity.0.y = 0.0;
+		}
+	}
 }
 
 fn check_for_player_death(
 	mut commands: Commands,
-	query: Query<(Entity, &Health, With<Player>)>,
+	settings: Res<LivesSettings>,
+	mut lives: ResMut<Lives>,
+	mut respawn_timer: ResMut<RespawnTimer>,
+	mut died_events: EventWriter<PlayerDied>,
+	mut run_ended_events: EventWriter<RunEnded>,
+	sprite_sheets: Res<SpriteSheets>,
+	// Not the spatial hash.  That's only rebuilt on the collision tick, so it can still list things that are gone, like the bullet that just hit us.
+	clearable: Query<(Entity, &Transform, Option<&Faction>), (Or<(With<Enemy>, With<Faction>, With<SpawnTelegraph>)>, Without<Boss>)>,
+	query: Query<(Entity, &Health, &Transform, With<Player>)>,
 ) {
 	//let (entity, player_health, _) = query.single();
-	if let Some((entity, player_health, _)) = query.iter().next() {
-		if player_health.0 <= 0.0 {
-			// Player is dead.  :'(
-			commands.entity(entity).despawn();
+	let (entity, player_health, transform, _) = match query.iter().next() {
+		Some(player) => player,
+		None => return,
+	};
+	if player_health.0 > 0.0 {
+		return;
+	}
+
+	// Player is dead.  :'(
+	commands.entity(entity).despawn();
+	lives.0 = lives.0.saturating_sub(1);
+	let position = transform.translation.truncate();
+	spawn_death_effect(&mut commands, &sprite_sheets, position, transform.scale.x * PLAYER_DEATH_EFFECT_SCALE);
+	died_events.send(PlayerDied {
+		position,
+		lives_remaining: lives.0,
+	});
+	if lives.0 == 0 {
+		run_ended_events.send(RunEnded);
+		return;
+	}
+
+	// Clear some room so we don't come back into a crowd: enemies, their bullets, and telegraphs about to hatch.  Bosses stay put.
+	for (other, other_transform, faction) in clearable.iter() {
+		if faction == Some(&Faction::Player) {
+			continue; // Our own spells can stay.
+		}
+		if other_transform.translation.truncate().distance(position) <= settings.death_clear_radius {
+			commands.entity(other).despawn();
 		}
 	}
-}
\ No newline at end of file
+	respawn_timer.0 = Some((Timer::from_seconds(settings.respawn_delay, false), position));
+}
+
+fn despawn_player(
+	mut commands: Commands,
+	query: Query<(Entity, With<Player>)>,
+) {
+	for (entity, _) in query.iter() {
+		commands.entity(entity).despawn();
+	}
+}
+fn apply_contact_damage(
+	mut commands: Commands,
+	settings: Res<PlayerDamageSettings>,
+	sprite_sheets: Res<SpriteSheets>,
+	mut collision_events: EventReader<CollisionEvent>,
+	mut damage_events: EventWriter<DamageEvent>,
+	mut player_damaged_events: EventWriter<PlayerDamaged>,
+	mut player_query: Query<(Entity, &Transform, &mut Velocity, Option<&Invulnerable>, With<Player>)>,
+	enemy_query: Query<(&Transform, &ContactDamage, Option<&DespawnOnContact>)>,
+) {
+	let (player_entity, player_transform, mut velocity, invulnerable, _) = match player_query.iter_mut().next() {
+		Some(p) => p,
+		None => return,
+	};
+	if invulnerable.is_some() {
+		return;
+	}
+
+	// Only take the first hit.  The invulnerability we add covers the rest of the pile-up.
+	for collision in collision_events.iter() {
+		let enemy_entity = match collision.between(LAYER_PLAYER, LAYER_ENEMY | LAYER_ENEMY_PROJECTILE) {
+			Some((p, e)) if p == player_entity => e,
+			_ => continue,
+		};
+		let (enemy_transform, contact_damage, despawn_on_contact) = match enemy_query.get(enemy_entity) {
+			Ok(enemy) => enemy,
+			Err(_) => continue,
+		};
+
+		damage_events.send(DamageEvent {
+			source: enemy_entity,
+			target: player_entity,
+			element: Element::Physical,
+			amount: contact_damage.0,
+			critical: false,
+			position: player_transform.translation.truncate(),
+		});
+		player_damaged_events.send(PlayerDamaged {
+			source: enemy_entity,
+			amount: contact_damage.0,
+		});
+
+		// Shove the player directly away from whatever hit them.
+		let away = (player_transform.translation - enemy_transform.translation).truncate().normalize_or_zero();
+		velocity.0 += away.extend(0.0) * settings.knockback_speed;
+
+		commands.entity(player_entity).insert(Invulnerable::new(settings.invulnerability_seconds, settings.flash_interval));
+		if despawn_on_contact.is_some() {
+			commands.entity(enemy_entity).despawn();
+			spawn_impact_effect(&mut commands, &sprite_sheets, enemy_transform.translation.truncate());
+		}
+		break;
+	}
+}
+
+fn tick_invulnerability(
+	mut commands: Commands,
+	time: Res<Time>,
+	hit_stop: Res<HitStop>,
+	mut query: Query<(Entity, &mut Invulnerable, &mut Visibility)>,
+) {
+	for (entity, mut invulnerable, mut visibility) in query.iter_mut() {
+		let delta = hit_stop.delta(&time);
+		invulnerable.timer.tick(delta);
+		invulnerable.flash_timer.tick(delta);
+		if invulnerable.timer.finished() {
+			visibility.is_visible = true;
+			commands.entity(entity).remove::<Invulnerable>();
+		} else if invulnerable.flash_timer.just_finished() {
+			visibility.is_visible = !visibility.is_visible;
+		}
+	}
+}
@@ -0,0 +1,190 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{DestroyOnOOB, Lifetime, SpriteSheets, Velocity, ENEMY_RENDER_PRIORITY};
+use crate::animation::{AnimationState, Animator};
+use crate::collision::{Collider, ColliderShape, LAYER_ENEMY, LAYER_ENEMY_PROJECTILE, LAYER_PLAYER, LAYER_PLAYER_PROJECTILE};
+use crate::damage::{ContactDamage, DespawnOnContact};
+use crate::feedback::HitStop;
+use crate::game_state::AppState;
+use crate::player::Player;
+
+const BULLET_RADIUS: f32 = 3.0;
+const BULLET_LIFETIME: f32 = 8.0; // Backstop.  Most leave the screen long before this.
+const BULLET_SCALE: f32 = 0.5;
+const BULLET_COLOR: Color = Color::rgb(1.0, 0.3, 0.9);
+
+// Bullet patterns for enemies and bosses.  The player's spells are their own thing, see spells.rs.  The bullets ride the usual Velocity/movement
+// and DestroyOnOOB/Lifetime cleanup, so all we do here is decide when and which way to fire.
+pub struct ProjectilePlugin;
+
+impl Plugin for ProjectilePlugin {
+	fn build(&self, app: &mut App) {
+		app.add_system_set(SystemSet::on_update(AppState::Playing).with_system(fire_emitters));
+		app.add_system_set(SystemSet::on_exit(AppState::Playing).with_system(despawn_projectiles));
+	}
+}
+
+// Components:
+// Who a projectile belongs to.  Decides which collision layer it's on and so what it can hit.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Faction {
+	Player,
+	Enemy,
+}
+
+impl Faction {
+	pub fn projectile_collider(&self, shape: ColliderShape) -> Collider {
+		match self {
+			Faction::Player => Collider::new(shape, LAYER_PLAYER_PROJECTILE, LAYER_ENEMY),
+			Faction::Enemy => Collider::new(shape, LAYER_ENEMY_PROJECTILE, LAYER_PLAYER),
+		}
+	}
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub enum BulletPattern {
+	RadialBurst { count: u32 }, // Evenly around a full circle.
+	Spiral { arms: u32, turn_per_shot: f32 }, // Like RadialBurst, but the whole thing rotates a little (degrees) each shot.
+	AimedSpread { count: u32, spread: f32 }, // Fan of `spread` degrees centred on the target.
+	Wave { amplitude: f32, period: u32 }, // One bullet at a time, swinging +/- amplitude degrees around the target every `period` shots.
+}
+
+impl BulletPattern {
+	// Unit directions for the `shot`th volley, given which way the target is.
+	pub fn directions(&self, aim: Vec2, shot: u32) -> Vec<Vec2> {
+		let base_angle = aim.y.atan2(aim.x);
+		let tau = 2.0 * std::f32::consts::PI;
+		let angles: Vec<f32> = match *self {
+			BulletPattern::RadialBurst { count } => {
+				(0..count).map(|i| base_angle + tau * i as f32 / count as f32).collect()
+			},
+			BulletPattern::Spiral { arms, turn_per_shot } => {
+				let turn = (turn_per_shot * shot as f32).to_radians();
+				(0..arms).map(|i| turn + tau * i as f32 / arms as f32).collect()
+			},
+			BulletPattern::AimedSpread { count, spread } => {
+				let spread = spread.to_radians();
+				(0..count).map(|i| {
+					if count > 1 { base_angle - spread / 2.0 + spread * (i as f32 / (count - 1) as f32) } else { base_angle }
+				}).collect()
+			},
+			BulletPattern::Wave { amplitude, period } => {
+				let phase = tau * shot as f32 / period.max(1) as f32;
+				vec![base_angle + amplitude.to_radians() * phase.sin()]
+			},
+		};
+		angles.into_iter().map(|angle| Vec2::new(angle.cos(), angle.sin())).collect()
+	}
+}
+
+// How an emitter is described in the asset files (enemy archetypes, boss phases).
+#[derive(Clone, Debug, Deserialize)]
+pub struct EmitterSpec {
+	pub pattern: BulletPattern,
+	pub interval: f32, // Seconds between volleys.
+	pub speed: f32,
+	pub damage: f32,
+	#[serde(default)]
+	pub range: Option<f32>, // Only fire when the target is within this distance.  None = always.
+}
+
+// Always fires enemy bullets, aimed at the player.
+#[derive(Component)]
+pub struct ProjectileEmitter {
+	pub spec: EmitterSpec,
+	pub timer: Timer,
+	pub shots_fired: u32,
+}
+
+impl ProjectileEmitter {
+	pub fn new(spec: EmitterSpec) -> Self {
+		ProjectileEmitter {
+			timer: Timer::from_seconds(spec.interval, true),
+			shots_fired: 0,
+			spec,
+		}
+	}
+}
+
+// Only hurts the player.  Bullets do ContactDamage, which the player listens for; enemies only take damage from spells.
+pub fn spawn_bullet(
+	commands: &mut Commands,
+	sprite_sheets: &SpriteSheets,
+	origin: Vec2,
+	velocity: Vec2,
+	damage: f32,
+) -> Entity {
+	commands
+		.spawn_bundle(SpriteSheetBundle {
+			texture_atlas: sprite_sheets.explosion.clone(),
+			sprite: TextureAtlasSprite {
+				color: BULLET_COLOR,
+				..Default::default()
+			},
+			transform: Transform {
+				translation: origin.extend(ENEMY_RENDER_PRIORITY),
+				scale: Vec3::new(BULLET_SCALE, BULLET_SCALE, 1.0),
+				..Default::default()
+			},
+			..Default::default()
+		})
+		.insert(Velocity(velocity.extend(0.0)))
+		.insert(DestroyOnOOB)
+		.insert(Lifetime(Timer::from_seconds(BULLET_LIFETIME, false)))
+		.insert(ContactDamage(damage))
+		.insert(DespawnOnContact)
+		.insert(Faction::Enemy)
+		.insert(Faction::Enemy.projectile_collider(ColliderShape::Circle(BULLET_RADIUS)))
+		.id()
+}
+
+// Systems:
+fn fire_emitters(
+	mut commands: Commands,
+	time: Res<Time>,
+	hit_stop: Res<HitStop>,
+	sprite_sheets: Res<SpriteSheets>,
+	player: Query<(&Transform, With<Player>)>,
+	mut emitters: Query<(&Transform, &mut ProjectileEmitter, Option<&mut Animator>)>,
+) {
+	let player_position = player.iter().next().map(|(tf, _)| tf.translation.truncate());
+
+	for (transform, mut emitter, animator) in emitters.iter_mut() {
+		emitter.timer.tick(hit_stop.delta(&time));
+		if !emitter.timer.just_finished() {
+			continue;
+		}
+
+		let origin = transform.translation.truncate();
+		// Point at the player.  With nobody to shoot, fire along our own facing.
+		let target = player_position;
+		if let (Some(range), Some(target)) = (emitter.spec.range, target) {
+			if origin.distance(target) > range {
+				continue;
+			}
+		}
+		let aim = match target {
+			Some(target) => (target - origin).normalize_or_zero(),
+			None => (transform.rotation * Vec3::X).truncate(),
+		};
+
+		for direction in emitter.spec.pattern.directions(aim, emitter.shots_fired) {
+			spawn_bullet(&mut commands, &sprite_sheets, origin, direction * emitter.spec.speed, emitter.spec.damage);
+		}
+		emitter.shots_fired += 1;
+		if let Some(mut animator) = animator {
+			animator.trigger(AnimationState::Cast);
+		}
+	}
+}
+
+// Faction covers spell projectiles as well as bullets.
+fn despawn_projectiles(
+	mut commands: Commands,
+	projectiles: Query<(Entity, With<Faction>)>,
+) {
+	for (entity, _) in projectiles.iter() {
+		commands.entity(entity).despawn();
+	}
+}
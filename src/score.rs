@@ -0,0 +1,346 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::user_data_path;
+use crate::enemy::{EnemyKilled, WaveCleared};
+use crate::feedback::HitStop;
+use crate::game_state::AppState;
+
+const HIGH_SCORE_FILE: &str = "high_scores.ron";
+const HIGH_SCORE_ENTRIES: usize = 10;
+const MAX_NAME_LENGTH: usize = 12;
+const DEFAULT_NAME: &str = "Wizard";
+const SCORE_FONT: &str = "OpenSans-Regular.ttf";
+
+// Points for kills (times the combo multiplier) and for clearing waves, plus the local high-score table.
+// Kills and wave clears come in as events from EnemyPlugin, so nothing here needs to know about enemies.
+pub struct ScorePlugin;
+
+impl Plugin for ScorePlugin {
+	fn build(&self, app: &mut App) {
+		app.insert_resource(ScoreSettings::default());
+		app.insert_resource(Score::default());
+		app.insert_resource(HighScoreTable::load_or_default(&high_score_path()));
+		app.insert_resource(NameEntry::default());
+		app.add_system_set(SystemSet::on_enter(AppState::Playing).with_system(reset_score));
+		app.add_system_set(
+			SystemSet::on_update(AppState::Playing)
+				.with_system(score_kills)
+				.with_system(score_wave_clears)
+				.with_system(decay_combo)
+		);
+		app.add_system_set(SystemSet::on_enter(AppState::GameOver).with_system(show_high_scores));
+		app.add_system_set(SystemSet::on_update(AppState::GameOver).with_system(enter_name));
+		app.add_system_set(SystemSet::on_exit(AppState::GameOver).with_system(finish_high_scores));
+	}
+}
+
+// Resources:
+pub struct ScoreSettings {
+	pub combo_window: f32, // Seconds after a kill that the next kill still counts toward the combo.
+	pub multiplier_per_kill: f32, // Added to the multiplier for each kill in a combo.
+	pub max_multiplier: f32,
+	pub multiplier_decay: f32, // Per second, once the combo window runs out.
+	pub wave_clear_bonus: u64, // Times the wave number.
+}
+
+impl Default for ScoreSettings {
+	fn default() -> Self {
+		ScoreSettings {
+			combo_window: 2.0,
+			multiplier_per_kill: 0.1,
+			max_multiplier: 5.0,
+			multiplier_decay: 1.0,
+			wave_clear_bonus: 100,
+		}
+	}
+}
+
+// The current run.  Plain data with no ECS in the way, so it's easy to poke at.
+#[derive(Clone, Debug)]
+pub struct Score {
+	pub points: u64,
+	pub combo: u32, // Kills in the current chain.  0 once the window runs out.
+	pub multiplier: f32,
+	pub combo_time_remaining: f32,
+	pub waves_cleared: u32,
+}
+
+impl Default for Score {
+	fn default() -> Self {
+		Score {
+			points: 0,
+			combo: 0,
+			multiplier: 1.0,
+			combo_time_remaining: 0.0,
+			waves_cleared: 0,
+		}
+	}
+}
+
+impl Score {
+	// Returns the points actually awarded.
+	pub fn register_kill(&mut self, base_points: u32, settings: &ScoreSettings) -> u64 {
+		if self.combo > 0 {
+			self.multiplier = (self.multiplier + settings.multiplier_per_kill).min(settings.max_multiplier);
+		}
+		self.combo += 1;
+		self.combo_time_remaining = settings.combo_window;
+
+		let awarded = (base_points as f32 * self.multiplier).round() as u64;
+		self.points += awarded;
+		awarded
+	}
+
+	pub fn register_wave_clear(&mut self, wave: u32, settings: &ScoreSettings) -> u64 {
+		let awarded = settings.wave_clear_bonus * wave as u64;
+		self.points += awarded;
+		self.waves_cleared = self.waves_cleared.max(wave);
+		awarded
+	}
+
+	pub fn tick(&mut self, dt: f32, settings: &ScoreSettings) {
+		if self.combo_time_remaining > 0.0 {
+			self.combo_time_remaining = (self.combo_time_remaining - dt).max(0.0);
+			return;
+		}
+		// Chain's broken.  The multiplier bleeds away rather than dropping straight to 1, so a quick recovery still pays.
+		self.combo = 0;
+		self.multiplier = (self.multiplier - settings.multiplier_decay * dt).max(1.0);
+	}
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HighScoreEntry {
+	pub name: String,
+	pub score: u64,
+	pub waves_cleared: u32,
+}
+
+// Best first, at most HIGH_SCORE_ENTRIES long.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct HighScoreTable {
+	pub entries: Vec<HighScoreEntry>,
+}
+
+impl HighScoreTable {
+	// Same deal as the input bindings: a missing or broken file just means an empty table.
+	pub fn load_or_default(path: &Path) -> Self {
+		match std::fs::read_to_string(path) {
+			Ok(contents) => match ron::de::from_str(&contents) {
+				Ok(table) => table,
+				Err(e) => {
+					warn!("Failed to parse high scores in {:?}: {}.  Starting a new table.", path, e);
+					HighScoreTable::default()
+				}
+			},
+			Err(_) => HighScoreTable::default(), // First run.  Nothing to complain about.
+		}
+	}
+
+	pub fn save(&self, path: &Path) -> Result<(), String> {
+		if let Some(dir) = path.parent() {
+			std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+		}
+		let contents = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()).map_err(|e| e.to_string())?;
+		std::fs::write(path, contents).map_err(|e| e.to_string())
+	}
+
+	pub fn qualifies(&self, score: u64) -> bool {
+		score > 0 && (self.entries.len() < HIGH_SCORE_ENTRIES || self.entries.iter().any(|e| score > e.score))
+	}
+
+	pub fn insert(&mut self, entry: HighScoreEntry) {
+		// Ties go below the existing entry.  First to get there keeps the spot.
+		let index = self.entries.iter().position(|e| entry.score > e.score).unwrap_or(self.entries.len());
+		self.entries.insert(index, entry);
+		self.entries.truncate(HIGH_SCORE_ENTRIES);
+	}
+}
+
+fn high_score_path() -> PathBuf {
+	user_data_path(HIGH_SCORE_FILE)
+}
+
+// Typing a name for the table on the game over screen.
+#[derive(Default)]
+struct NameEntry {
+	active: bool,
+	name: String,
+}
+
+// Components:
+#[derive(Component)]
+struct HighScoreUi;
+
+#[derive(Component)]
+struct NameEntryText;
+
+// Systems:
+fn reset_score(
+	mut score: ResMut<Score>,
+) {
+	*score = Score::default();
+}
+
+fn score_kills(
+	settings: Res<ScoreSettings>,
+	mut score: ResMut<Score>,
+	mut killed_events: EventReader<EnemyKilled>,
+) {
+	for event in killed_events.iter() {
+		score.register_kill(event.score, &settings);
+	}
+}
+
+fn score_wave_clears(
+	settings: Res<ScoreSettings>,
+	mut score: ResMut<Score>,
+	mut wave_cleared_events: EventReader<WaveCleared>,
+) {
+	for event in wave_cleared_events.iter() {
+		score.register_wave_clear(event.wave, &settings);
+	}
+}
+
+fn decay_combo(
+	time: Res<Time>,
+	hit_stop: Res<HitStop>,
+	settings: Res<ScoreSettings>,
+	mut score: ResMut<Score>,
+) {
+	// Don't touch it when there's nothing to decay, or the HUD would redraw every frame.
+	if score.combo > 0 || score.multiplier > 1.0 {
+		score.tick(hit_stop.delta_seconds(&time), &settings);
+	}
+}
+
+fn show_high_scores(
+	mut commands: Commands,
+	asset_server: Res<AssetServer>,
+	score: Res<Score>,
+	table: Res<HighScoreTable>,
+	mut name_entry: ResMut<NameEntry>,
+) {
+	name_entry.active = table.qualifies(score.points);
+	name_entry.name.clear();
+
+	let font = asset_server.load(SCORE_FONT);
+	let style = TextStyle {
+		font,
+		font_size: 32.0,
+		color: Color::WHITE,
+	};
+	let mut lines = vec![format!("Score: {}", score.points), String::new()];
+	lines.extend(table.entries.iter().enumerate().map(|(i, e)| format!("{}. {}  {}  (wave {})", i + 1, e.name, e.score, e.waves_cleared)));
+
+	commands
+		.spawn_bundle(NodeBundle {
+			style: Style {
+				position_type: PositionType::Absolute,
+				position: Rect {
+					left: Val::Percent(35.0),
+					bottom: Val::Percent(5.0),
+					..Default::default()
+				},
+				flex_direction: FlexDirection::ColumnReverse, // Top to bottom.
+				..Default::default()
+			},
+			color: UiColor(Color::NONE),
+			..Default::default()
+		})
+		.insert(HighScoreUi)
+		.with_children(|parent| {
+			if name_entry.active {
+				parent
+					.spawn_bundle(TextBundle {
+						text: Text::with_section(name_prompt(""), style.clone(), Default::default()),
+						..Default::default()
+					})
+					.insert(NameEntryText);
+			}
+			for line in lines {
+				parent.spawn_bundle(TextBundle {
+					text: Text::with_section(line, style.clone(), Default::default()),
+					..Default::default()
+				});
+			}
+		});
+}
+
+fn name_prompt(name: &str) -> String {
+	format!("New high score!  Name: {}_  (Enter to save)", name)
+}
+
+// Raw keyboard on purpose.  This is text, not a game action, so rebinding doesn't apply.
+fn enter_name(
+	mut received_characters: EventReader<ReceivedCharacter>,
+	keys: Res<Input<KeyCode>>,
+	mut name_entry: ResMut<NameEntry>,
+	score: Res<Score>,
+	mut table: ResMut<HighScoreTable>,
+	mut text_query: Query<&mut Text, With<NameEntryText>>,
+) {
+	if !name_entry.active {
+		return;
+	}
+
+	let mut changed = false;
+	for event in received_characters.iter() {
+		if !event.char.is_control() && name_entry.name.chars().count() < MAX_NAME_LENGTH {
+			name_entry.name.push(event.char);
+			changed = true;
+		}
+	}
+	if keys.just_pressed(KeyCode::Back) {
+		name_entry.name.pop();
+		changed = true;
+	}
+
+	if keys.just_pressed(KeyCode::Return) {
+		submit_high_score(&mut name_entry, &score, &mut table);
+		for mut text in text_query.iter_mut() {
+			text.sections[0].value = "Saved!".to_string();
+		}
+	} else if changed {
+		for mut text in text_query.iter_mut() {
+			text.sections[0].value = name_prompt(&name_entry.name);
+		}
+	}
+}
+
+// Leaving the screen without pressing Enter still keeps the score.
+fn finish_high_scores(
+	mut commands: Commands,
+	mut name_entry: ResMut<NameEntry>,
+	score: Res<Score>,
+	mut table: ResMut<HighScoreTable>,
+	ui_query: Query<(Entity, With<HighScoreUi>)>,
+) {
+	if name_entry.active {
+		submit_high_score(&mut name_entry, &score, &mut table);
+	}
+	for (entity, _) in ui_query.iter() {
+		commands.entity(entity).despawn_recursive();
+	}
+}
+
+fn submit_high_score(
+	name_entry: &mut NameEntry,
+	score: &Score,
+	table: &mut HighScoreTable,
+) {
+	let name = name_entry.name.trim();
+	table.insert(HighScoreEntry {
+		name: if name.is_empty() { DEFAULT_NAME.to_string() } else { name.to_string() },
+		score: score.points,
+		waves_cleared: score.waves_cleared,
+	});
+	name_entry.active = false;
+
+	let path = high_score_path();
+	if let Err(e) = table.save(&path) {
+		warn!("Could not save high scores to {:?}: {}", path, e);
+	}
+}
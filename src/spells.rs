@@ -1,64 +1,348 @@
-use bevy::input::mouse::MouseButtonInput;
+use bevy::asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset};
 use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use rand::{Rng, thread_rng};
+use serde::Deserialize;
 
-use crate::{DestroyOnOOB, ENEMY_RENDER_PRIORITY, ScreenShake, SpriteSheets, Velocity};
+use crate::{DestroyOnOOB, Lifetime, SpriteDefinition, Velocity};
+use crate::animation::{AnimationLibrary, Animator};
+use crate::collision::ColliderShape;
+use crate::damage::Element;
+use crate::feedback::HitStop;
+use crate::game_state::{AppState, LoadingAssets};
+use crate::input::{Action, ActionState, Aim, CursorWorldPosition};
+use crate::particles::{ParticleEmitter, ParticleSpec};
 use crate::player::Player;
+use crate::projectile::Faction;
 
-const MAGIC_MISSILE_SPEED:f32 = 100.0f32;
+const PRIMARY_SPELL_PATH: &str = "spells/magic_missile.spell.ron";
+const SECONDARY_SPELL_PATH: &str = "spells/arcane_burst.spell.ron";
+const PROJECTILE_RADIUS: f32 = 4.0; // The missile head is much smaller than its 16x16 tile.
 
 pub struct SpellPlugin;
 
 impl Plugin for SpellPlugin {
 	fn build(&self, app: &mut App) {
-		app.add_system(cast_magic_missile);
+		app.add_asset::<SpellDefinition>();
+		app.init_asset_loader::<SpellDefinitionLoader>();
+		app.add_startup_system(setup_spellbook);
+		app.add_system_set(
+			SystemSet::on_enter(AppState::Playing)
+				.with_system(reset_cooldowns)
+				.with_system(wait_for_cast_release)
+		);
+		app.add_system_set(
+			SystemSet::on_update(AppState::Playing)
+				.with_system(tick_spell_cooldowns)
+				.with_system(regenerate_mana)
+				.with_system(cast_spells)
+		);
+		app.add_system(register_spell_animations);
 	}
 }
 
+// Assets:
+// Everything about a spell lives in assets/spells/*.spell.ron so we can balance without recompiling.
+// Edits to those files are hot reloaded, and because we look the definition up at cast time the next cast picks them up.
+#[derive(Debug, Deserialize, TypeUuid)]
+#[uuid = "4d9d1fb4-4ec7-42e6-8541-0c0c411aea6a"]
+pub struct SpellDefinition {
+	pub name: String,
+	pub sprite: SpriteDefinition,
+	pub speed: f32,
+	pub damage: f32,
+	pub element: Element,
+	#[serde(default)]
+	pub crit_chance: f32, // 0 to 1, rolled once per enemy hit.  Explosions don't crit.
+	#[serde(default = "default_crit_multiplier")]
+	pub crit_multiplier: f32,
+	pub cooldown: f32, // Seconds.
+	pub mana_cost: f32,
+	pub pierce: u32, // How many enemies the projectile passes through before it's used up.  0 = stops at the first.
+	#[serde(default = "one")]
+	pub projectile_count: u32,
+	pub spread: f32, // Degrees.  Fan width when firing several projectiles, random inaccuracy when firing one.
+	pub lifetime: f32, // Seconds before the projectile fizzles.
+	#[serde(default)]
+	pub on_hit: Vec<OnHitEffect>,
+	#[serde(default)]
+	pub trail: Option<ParticleSpec>,
+	#[serde(skip)]
+	pub atlas: Handle<TextureAtlas>, // Built by the loader from `sprite`.
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub enum OnHitEffect {
+	Explode { radius: f32, damage: f32 },
+	ScreenShake(f32), // Trauma, 0 to 1.  Adds up across hits.
+}
+
+fn one() -> u32 {
+	1
+}
+
+fn default_crit_multiplier() -> f32 {
+	2.0
+}
+
+#[derive(Default)]
+pub struct SpellDefinitionLoader;
+
+impl AssetLoader for SpellDefinitionLoader {
+	fn load<'a>(
+		&'a self,
+		bytes: &'a [u8],
+		load_context: &'a mut LoadContext,
+	) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+		Box::pin(async move {
+			let mut spell: SpellDefinition = ron::de::from_bytes(bytes)?;
+			let (atlas, texture_path) = spell.sprite.load_atlas(load_context, "atlas");
+			spell.atlas = atlas;
+			load_context.set_default_asset(LoadedAsset::new(spell).with_dependency(texture_path));
+			Ok(())
+		})
+	}
+
+	fn extensions(&self) -> &[&str] {
+		&["spell.ron"]
+	}
+}
+
+// Resources:
+pub struct SpellSlot {
+	pub spell: Handle<SpellDefinition>,
+	pub cooldown_remaining: f32,
+}
+
+pub struct Spellbook {
+	pub primary: SpellSlot,
+	pub secondary: SpellSlot,
+}
+
+// Components:
 #[derive(Component)]
 pub struct SpellEffect {
 	pub base_damage: f32,
-	pub element_type: usize, // Swap this with enum.
+	pub element: Element,
+	pub crit_chance: f32,
+	pub crit_multiplier: f32,
 }
 
-// We could make this system listen for button inputs OR we could define a function that spawns the spell.
-fn cast_magic_missile(
+// Tracks what a projectile has already hit so overlapping for several frames only counts once.
+#[derive(Component)]
+pub struct Projectile {
+	pub pierce_remaining: u32,
+	pub hit_entities: Vec<Entity>,
+	pub on_hit: Vec<OnHitEffect>,
+	pub spent: bool, // Out of pierce and waiting to be despawned.
+}
+
+#[derive(Component)]
+pub struct Mana {
+	pub current: f32,
+	pub max: f32,
+	pub regen_per_second: f32,
+}
+
+impl Default for Mana {
+	fn default() -> Self {
+		Mana {
+			current: 100.0,
+			max: 100.0,
+			regen_per_second: 10.0,
+		}
+	}
+}
+
+// Systems:
+fn setup_spellbook(
 	mut commands: Commands,
-	windows: Res<Windows>,
-	mut screen_shake: ResMut<ScreenShake>,
-	mouse_button_input: Res<Input<MouseButton>>,
-	atlas_assets: Res<Assets<TextureAtlas>>,
-	sprite_sheets: Res<SpriteSheets>,
-	player: Query<(&Transform, With<Player>)>, // Used to give us direction for the attack.
+	asset_server: Res<AssetServer>,
+	mut loading: ResMut<LoadingAssets>,
+) {
+	if let Err(e) = asset_server.watch_for_changes() {
+		warn!("Spell hot reloading is unavailable: {:?}", e);
+	}
+
+	let primary: Handle<SpellDefinition> = asset_server.load(PRIMARY_SPELL_PATH);
+	let secondary: Handle<SpellDefinition> = asset_server.load(SECONDARY_SPELL_PATH);
+	loading.0.push(primary.clone_untyped());
+	loading.0.push(secondary.clone_untyped());
+	commands.insert_resource(Spellbook {
+		primary: SpellSlot {
+			spell: primary,
+			cooldown_remaining: 0.0,
+		},
+		secondary: SpellSlot {
+			spell: secondary,
+			cooldown_remaining: 0.0,
+		},
+	});
+}
+
+// Same as enemies: spell sheets go in the animation library whenever a spell file (re)loads.
+fn register_spell_animations(
+	mut asset_events: EventReader<AssetEvent<SpellDefinition>>,
+	spells: Res<Assets<SpellDefinition>>,
+	mut library: ResMut<AnimationLibrary>,
 ) {
-	if mouse_button_input.just_pressed(MouseButton::Left) {
-		let window = windows.get_primary().expect("Could not acquire primary game window.");
-		if let Some(mouse_position) = window.cursor_position() {
-			let (player_transform, _) = player.single();
-			// Mouse Position is in Window space.
-			let delta = Vec3::new(((mouse_position.x/window.width())-0.5) - player_transform.translation.x, ((mouse_position.y/window.height())-0.5) - player_transform.translation.y, 0.0).normalize() * MAGIC_MISSILE_SPEED;
-			let angle = delta.y.atan2(delta.x);  // TODO: This isn't quite right.
-
-			commands
-				.spawn_bundle(SpriteSheetBundle {
-					texture_atlas: atlas_assets.get_handle(&sprite_sheets.magic_missile),
-					transform: Transform {
-						translation: player_transform.translation,
-						rotation: Quat::from_rotation_z(-angle),
-						..Default::default()
-					},
-					..Default::default()
-				})
-				.insert(DestroyOnOOB)
-				.insert(Timer::from_seconds(0.1, true))
-				.insert(Velocity(delta))
-				.insert(SpellEffect {
-					base_damage: 1.0f32,
-					element_type: 0,
-				});
-
-			// Shake
-			screen_shake.magnitude += 20.0;
+	for event in asset_events.iter() {
+		if let AssetEvent::Created { handle } | AssetEvent::Modified { handle } = event {
+			if let Some(spell) = spells.get(handle) {
+				library.register(&spell.atlas, spell.sprite.clips());
+			}
 		}
 	}
+}
 
-}
\ No newline at end of file
+fn reset_cooldowns(
+	mut spellbook: ResMut<Spellbook>,
+) {
+	spellbook.primary.cooldown_remaining = 0.0;
+	spellbook.secondary.cooldown_remaining = 0.0;
+}
+
+// Casting is hold-to-fire, so the click that started (or restarted) the run would otherwise go straight into a spell.
+fn wait_for_cast_release(
+	mut actions: ResMut<ActionState>,
+) {
+	actions.ignore_until_released(Action::CastPrimary);
+	actions.ignore_until_released(Action::CastSecondary);
+}
+
+fn tick_spell_cooldowns(
+	time: Res<Time>,
+	hit_stop: Res<HitStop>,
+	mut spellbook: ResMut<Spellbook>,
+) {
+	// Leave it untouched when both are ready, so the HUD's change detection can skip it.
+	if spellbook.primary.cooldown_remaining <= 0.0 && spellbook.secondary.cooldown_remaining <= 0.0 {
+		return;
+	}
+	let dt = hit_stop.delta_seconds(&time);
+	spellbook.primary.cooldown_remaining = (spellbook.primary.cooldown_remaining - dt).max(0.0);
+	spellbook.secondary.cooldown_remaining = (spellbook.secondary.cooldown_remaining - dt).max(0.0);
+}
+
+fn regenerate_mana(
+	time: Res<Time>,
+	hit_stop: Res<HitStop>,
+	mut query: Query<&mut Mana>,
+) {
+	for mut mana in query.iter_mut() {
+		// Full mana shouldn't count as a change.
+		if mana.current >= mana.max {
+			continue;
+		}
+		mana.current = (mana.current + mana.regen_per_second * hit_stop.delta_seconds(&time)).min(mana.max);
+	}
+}
+
+fn cast_spells(
+	mut commands: Commands,
+	mut spellbook: ResMut<Spellbook>,
+	actions: Res<ActionState>,
+	cursor_world: Res<CursorWorldPosition>,
+	spells: Res<Assets<SpellDefinition>>,
+	mut player: Query<(&Transform, &mut Mana, With<Player>)>, // Used to give us direction for the attack.
+) {
+	let (player_transform, mut mana, _) = match player.iter_mut().next() {
+		Some(p) => p,
+		None => return,
+	};
+
+	// Only borrow the spellbook mutably when something actually gets cast, so the HUD doesn't see a change every frame.
+	for action in [Action::CastPrimary, Action::CastSecondary] {
+		let slot = if action == Action::CastPrimary { &spellbook.primary } else { &spellbook.secondary };
+		if !actions.pressed(action) || slot.cooldown_remaining > 0.0 {
+			continue;
+		}
+		// Might still be loading.
+		let spell = match spells.get(&slot.spell) {
+			Some(spell) => spell,
+			None => continue,
+		};
+		// Only complain about mana on the initial press, not every frame it's held.
+		if mana.current < spell.mana_cost {
+			if actions.just_pressed(action) {
+				info!("Not enough mana for {}.", spell.name);
+			}
+			continue;
+		}
+
+		let player_position = Vec2::new(player_transform.translation.x, player_transform.translation.y);
+		let aim_direction = match actions.aim {
+			Aim::Cursor(_) => cursor_world.0.map(|target| target - player_position),
+			Aim::Direction(direction) => Some(direction),
+			Aim::None => None,
+		}.unwrap_or(Vec2::ZERO).normalize_or_zero();
+
+		// Clicking right on top of the wizard gives us no direction.  Don't fire.
+		if aim_direction == Vec2::ZERO {
+			continue;
+		}
+
+		spawn_spell_projectiles(&mut commands, spell, player_transform.translation, aim_direction);
+		mana.current -= spell.mana_cost;
+		let slot = if action == Action::CastPrimary { &mut spellbook.primary } else { &mut spellbook.secondary };
+		slot.cooldown_remaining = spell.cooldown;
+	}
+}
+
+fn spawn_spell_projectiles(
+	commands: &mut Commands,
+	spell: &SpellDefinition,
+	origin: Vec3,
+	aim_direction: Vec2,
+) {
+	let base_angle = aim_direction.y.atan2(aim_direction.x);
+	let spread = spell.spread.to_radians();
+	let count = spell.projectile_count.max(1);
+
+	let mut rng = thread_rng();
+	for i in 0..count {
+		// Several projectiles fan out evenly.  A single one wobbles randomly inside the spread.
+		let offset = if count > 1 {
+			-spread / 2.0 + spread * (i as f32 / (count - 1) as f32)
+		} else if spread > 0.0 {
+			rng.gen_range(-spread / 2.0, spread / 2.0)
+		} else {
+			0.0
+		};
+		let angle = base_angle + offset; // Sprite points along +X.
+		let velocity = Vec3::new(angle.cos(), angle.sin(), 0.0) * spell.speed;
+
+		let projectile = commands
+			.spawn_bundle(SpriteSheetBundle {
+				texture_atlas: spell.atlas.clone(),
+				transform: Transform {
+					translation: origin,
+					rotation: Quat::from_rotation_z(angle),
+					scale: Vec3::new(spell.sprite.scale, spell.sprite.scale, 1.0),
+				},
+				..Default::default()
+			})
+			.insert(DestroyOnOOB)
+			.insert(Lifetime(Timer::from_seconds(spell.lifetime, false)))
+			.insert(Animator::default())
+			.insert(Velocity(velocity))
+			.insert(SpellEffect {
+				base_damage: spell.damage,
+				element: spell.element,
+				crit_chance: spell.crit_chance,
+				crit_multiplier: spell.crit_multiplier,
+			})
+			.insert(Projectile {
+				pierce_remaining: spell.pierce,
+				hit_entities: Vec::new(),
+				on_hit: spell.on_hit.clone(),
+				spent: false,
+			})
+			.insert(Faction::Player)
+			.insert(Faction::Player.projectile_collider(ColliderShape::Circle(PROJECTILE_RADIUS)))
+			.id();
+		if let Some(trail) = &spell.trail {
+			commands.entity(projectile).insert(ParticleEmitter::new(trail.clone()));
+		}
+	}
+}
@@ -1,20 +1,63 @@
 use bevy::prelude::*;
 
+const ANNOUNCEMENT_FONT: &str = "OpenSans-Regular.ttf";
+
+// Announcements and toasts.  Anything can send a UIText event; we queue them up per anchor, show as many as the anchor
+// has room for, and fade each one in, hold it, and fade it out.  A higher priority message bumps a lower one off the screen.
 pub struct TextDisplayPlugin;
 
 impl Plugin for TextDisplayPlugin {
 	fn build(&self, app: &mut App) {
-		app.add_system(spawn_ui_text);
-		app.add_system(update_ui_text);
+		app.add_event::<UIText>();
+		app.insert_resource(AnnouncementQueue::default());
+		app.add_startup_system(spawn_anchors);
+		app.add_system(queue_ui_text.label(TextDisplaySystem::Queue));
+		app.add_system(spawn_ui_text.label(TextDisplaySystem::Spawn).after(TextDisplaySystem::Queue));
+		app.add_system(update_ui_text.after(TextDisplaySystem::Spawn));
 	}
 }
 
-#[derive(Component, Clone)]
+#[derive(SystemLabel, Clone, Hash, Debug, PartialEq, Eq)]
+enum TextDisplaySystem {
+	Queue,
+	Spawn,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TextAnchor {
+	Top, // Toasts.  Several at once, newest at the bottom.
+	Center, // Big announcements.  One at a time.
+	Bottom,
+}
+
+impl TextAnchor {
+	fn capacity(&self) -> usize {
+		match self {
+			TextAnchor::Center => 1,
+			TextAnchor::Top | TextAnchor::Bottom => 3,
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TextPriority {
+	Low,
+	Normal,
+	High, // Boss intros and the like.
+}
+
+// Events:
+// Send one of these to put some text on screen.
+#[derive(Clone, Debug)]
 pub struct UIText {
 	text: String,
 	color: Color,
 	size: f32,
-	fade_time_in_seconds: f32,
+	fade_in_seconds: f32,
+	hold_seconds: f32,
+	fade_out_seconds: f32,
+	anchor: TextAnchor,
+	priority: TextPriority,
 }
 
 impl Default for UIText {
@@ -22,8 +65,12 @@ impl Default for UIText {
 		UIText {
 			text: String::new(),
 			color: Color::WHITE,
-			size: 10.0f32,
-			fade_time_in_seconds: 1.0f32,
+			size: 100.0f32,
+			fade_in_seconds: 0.25f32,
+			hold_seconds: 1.5f32,
+			fade_out_seconds: 0.5f32,
+			anchor: TextAnchor::Center,
+			priority: TextPriority::Normal,
 		}
 	}
 }
@@ -34,7 +81,9 @@ impl UIText {
 			text: txt,
 			color,
 			size,
-			fade_time_in_seconds
+			fade_in_seconds: fade_time_in_seconds,
+			fade_out_seconds: fade_time_in_seconds,
+			..Default::default()
 		}
 	}
 
@@ -44,106 +93,187 @@ impl UIText {
 			..Default::default()
 		}
 	}
+
+	pub fn with_color(mut self, color: Color) -> Self {
+		self.color = color;
+		self
+	}
+
+	pub fn with_size(mut self, size: f32) -> Self {
+		self.size = size;
+		self
+	}
+
+	pub fn with_timing(mut self, fade_in_seconds: f32, hold_seconds: f32, fade_out_seconds: f32) -> Self {
+		self.fade_in_seconds = fade_in_seconds;
+		self.hold_seconds = hold_seconds;
+		self.fade_out_seconds = fade_out_seconds;
+		self
+	}
+
+	pub fn with_anchor(mut self, anchor: TextAnchor) -> Self {
+		self.anchor = anchor;
+		self
+	}
+
+	pub fn with_priority(mut self, priority: TextPriority) -> Self {
+		self.priority = priority;
+		self
+	}
+
+	fn duration(&self) -> f32 {
+		self.fade_in_seconds + self.hold_seconds + self.fade_out_seconds
+	}
+
+	// 0 -> 1 over the fade in, 1 for the hold, 1 -> 0 over the fade out.
+	fn alpha_at(&self, elapsed: f32) -> f32 {
+		if elapsed < self.fade_in_seconds {
+			elapsed / self.fade_in_seconds
+		} else if elapsed < self.fade_in_seconds + self.hold_seconds {
+			1.0
+		} else {
+			let fade_out_elapsed = elapsed - self.fade_in_seconds - self.hold_seconds;
+			1.0 - (fade_out_elapsed / self.fade_out_seconds.max(1e-6)).min(1.0)
+		}
+	}
 }
 
-fn spawn_ui_text(
+// Resources:
+// Waiting for room on their anchor.  Kept in arrival order; we pick the highest priority out of it.
+#[derive(Default)]
+struct AnnouncementQueue(Vec<UIText>);
+
+// Components:
+// One flex column per anchor.  Text goes in as children so stacking is just layout.
+#[derive(Component)]
+struct AnchorNode(TextAnchor);
+
+#[derive(Component)]
+struct ShownText {
+	message: UIText,
+	elapsed: f32,
+}
+
+// Systems:
+fn spawn_anchors(
 	mut commands: Commands,
-	mut asset_server: ResMut<AssetServer>,
-	unspawned_text: Query<(Entity, &UIText, Without<Style>)>,
 ) {
-	// This is slightly inefficient because we're removing text and adding it again instead of just adding the component, but...
-	for (entity, txt, _) in unspawned_text.iter() {
-		commands.spawn_bundle(
-			TextBundle {
+	for (anchor, justify) in [(TextAnchor::Top, JustifyContent::FlexStart), (TextAnchor::Center, JustifyContent::Center), (TextAnchor::Bottom, JustifyContent::FlexEnd)] {
+		commands
+			.spawn_bundle(NodeBundle {
 				style: Style {
-					align_self: AlignSelf::Center,
+					size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
 					position_type: PositionType::Absolute,
-					position: Rect {
-						left: Val::Percent(10.0),
-						top: Val::Percent(100.0),
-						//top: Val::Auto,
-						//bottom: Val::Px(-50.0),
-						right: Val::Auto,
-						bottom: Val::Auto,
-					},
+					flex_direction: FlexDirection::ColumnReverse, // Top to bottom, so FlexStart is the top of the screen.
+					justify_content: justify,
+					align_items: AlignItems::Center,
 					..Default::default()
 				},
-				// Use the `Text::with_section` constructor
-				text: Text::with_section(
-					txt.text.clone(),
-					TextStyle {
-						font: asset_server.load("OpenSans-Regular.ttf"),
-						font_size: 100.0,
-						color: Color::WHITE,
-					},
-					// Note: You can use `Default::default()` in place of the `TextAlignment`
-					TextAlignment {
-						horizontal: HorizontalAlign::Center,
-						vertical: VerticalAlign::Center,
-						//..Default::default()
-					},
-				),
+				color: UiColor(Color::NONE),
 				..Default::default()
+			})
+			.insert(AnchorNode(anchor));
+	}
+}
+
+fn queue_ui_text(
+	mut queue: ResMut<AnnouncementQueue>,
+	mut ui_text_events: EventReader<UIText>,
+) {
+	for message in ui_text_events.iter() {
+		queue.0.push(message.clone());
+	}
+}
+
+fn spawn_ui_text(
+	mut commands: Commands,
+	asset_server: Res<AssetServer>,
+	mut queue: ResMut<AnnouncementQueue>,
+	anchors: Query<(Entity, &AnchorNode)>,
+	shown: Query<(Entity, &ShownText)>,
+) {
+	if queue.0.is_empty() {
+		return;
+	}
+
+	for (anchor_entity, anchor) in anchors.iter() {
+		let anchor = anchor.0;
+		let mut on_screen: Vec<(Entity, TextPriority)> = shown.iter()
+			.filter(|(_, shown)| shown.message.anchor == anchor)
+			.map(|(entity, shown)| (entity, shown.message.priority))
+			.collect();
+
+		loop {
+			// Highest priority first.  max_by_key returns the last of equals, so go backwards to keep first-come-first-served.
+			let next = queue.0.iter().enumerate().rev()
+				.filter(|(_, message)| message.anchor == anchor)
+				.max_by_key(|(_, message)| message.priority)
+				.map(|(index, _)| index);
+			let index = match next {
+				Some(index) => index,
+				None => break,
+			};
+
+			if on_screen.len() >= anchor.capacity() {
+				// Full.  Only get on if we outrank something that's already up, and then that one has to go.
+				let lowest = on_screen.iter().enumerate().min_by_key(|(_, (_, priority))| *priority).map(|(i, (e, p))| (i, *e, *p));
+				match lowest {
+					Some((i, entity, priority)) if priority < queue.0[index].priority => {
+						commands.entity(entity).despawn_recursive();
+						on_screen.remove(i);
+					},
+					_ => break,
+				}
 			}
-		).insert(txt.clone());
-		// Unspawn the old entry:
-		commands.entity(entity).despawn();
+
+			let message = queue.0.remove(index);
+			let mut color = message.color;
+			color.set_a(0.0); // Fades in from nothing.
+			let text_entity = commands
+				.spawn_bundle(TextBundle {
+					style: Style {
+						margin: Rect::all(Val::Px(4.0)),
+						..Default::default()
+					},
+					text: Text::with_section(
+						message.text.clone(),
+						TextStyle {
+							font: asset_server.load(ANNOUNCEMENT_FONT),
+							font_size: message.size,
+							color,
+						},
+						TextAlignment {
+							horizontal: HorizontalAlign::Center,
+							vertical: VerticalAlign::Center,
+						},
+					),
+					..Default::default()
+				})
+				.id();
+			on_screen.push((text_entity, message.priority));
+			commands.entity(text_entity).insert(ShownText {
+				message,
+				elapsed: 0.0,
+			});
+			commands.entity(anchor_entity).push_children(&[text_entity]);
+		}
 	}
 }
 
-// This is hacky and bad.  Replace it.
 fn update_ui_text(
 	mut commands: Commands,
-	mut text: Query<(Entity, &UIText, &mut Style)>,
+	time: Res<Time>,
+	mut text: Query<(Entity, &mut ShownText, &mut Text)>,
 ) {
-	for (entity, ui_text, mut style) in text.iter_mut() {
-		let dist_to_top = if let Val::Percent(p) = style.position.top {
-			p - 50.0
-		} else {
-			0.0 // This can't happen.
-		};
-		style.position.top += -dist_to_top*0.05;
-		//style.position.bottom += dist_to_top*0.1;
-
-		if dist_to_top < 1.5e-3 {
-			// Start to fade away.
-			commands.entity(entity).despawn();
-			info!("Despawning text.");
+	for (entity, mut shown, mut text) in text.iter_mut() {
+		shown.elapsed += time.delta_seconds();
+		if shown.elapsed >= shown.message.duration() {
+			commands.entity(entity).despawn_recursive();
+			continue;
 		}
+
+		let mut color = shown.message.color;
+		color.set_a(shown.message.color.a() * shown.message.alpha_at(shown.elapsed));
+		text.sections[0].style.color = color;
 	}
 }
-
-/*
- commands
-        .spawn_bundle(TextBundle {
-            style: Style {
-                align_self: AlignSelf::FlexEnd,
-                ..Default::default()
-            },
-            // Use `Text` directly
-            text: Text {
-                // Construct a `Vec` of `TextSection`s
-                sections: vec![
-                    TextSection {
-                        value: "FPS: ".to_string(),
-                        style: TextStyle {
-                            font: asset_server.load("fonts/FiraSans-Bold.ttf"),
-                            font_size: 60.0,
-                            color: Color::WHITE,
-                        },
-                    },
-                    TextSection {
-                        value: "".to_string(),
-                        style: TextStyle {
-                            font: asset_server.load("fonts/FiraMono-Medium.ttf"),
-                            font_size: 60.0,
-                            color: Color::GOLD,
-                        },
-                    },
-                ],
-                ..Default::default()
-            },
-            ..Default::default()
-        })
-        .insert(FpsText);
-*/
\ No newline at end of file
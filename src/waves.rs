@@ -0,0 +1,376 @@
+use bevy::asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use serde::Deserialize;
+
+use crate::boss::BossSpec;
+
+pub const WAVE_SCRIPT_PATH: &str = "waves.script.ron";
+
+// Assets:
+// assets/waves.script.ron lists the hand-made waves in order.  Once we run off the end, `endless` makes up new ones.
+#[derive(Clone, Debug, Deserialize, TypeUuid)]
+#[uuid = "7c2d0e5b-8a41-4c1f-b3e9-2f6a9d4c7e10"]
+pub struct WaveScript {
+	pub waves: Vec<WaveSpec>,
+	pub endless: EndlessWaves,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct WaveSpec {
+	pub groups: Vec<SpawnGroup>,
+	#[serde(default)]
+	pub boss: Option<BossSpec>, // Wave isn't over until the boss is dead, however many minions are left.
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SpawnGroup {
+	#[serde(default)]
+	pub archetype: Option<String>, // None = weighted random pick from what's allowed this wave.
+	pub count: u32,
+	pub interval: f32, // Seconds between spawns.  Ignored for Burst.
+	pub pattern: SpawnPattern,
+	#[serde(default)]
+	pub delay: f32, // Seconds after the wave starts before this group's first spawn.
+	#[serde(default)]
+	pub telegraph: Option<f32>, // Warning time before each enemy appears.  None = SpawnSettings default.
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub enum SpawnPattern {
+	Random, // Anywhere on screen.
+	ScreenEdge,
+	RingAroundPlayer { radius: f32 }, // Random spot on the ring each spawn.
+	Points(Vec<(f32, f32)>), // World positions, cycled through in order.
+	Burst { radius: f32 }, // The whole group at once, evenly spaced on a ring around the player.
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct EndlessWaves {
+	pub base_count: u32,
+	pub count_per_wave: f32, // Extra enemies per endless wave.
+	pub base_interval: f32,
+	pub interval_decay: f32, // Interval is multiplied by this each endless wave.
+	pub min_interval: f32,
+	pub patterns: Vec<SpawnPattern>, // One group per pattern, staggered.
+	pub group_stagger: f32, // Seconds between each group starting.
+	#[serde(default)]
+	pub boss_every: u32, // Every Nth endless wave also gets `boss`.  0 = never.
+	#[serde(default)]
+	pub boss: Option<EndlessBoss>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub enum EndlessBoss {
+	Wave(u32), // The boss from this scripted wave (numbered from 1), so it only has to be written out once.
+	Spec(BossSpec),
+}
+
+impl WaveScript {
+	// Waves are numbered from 1.
+	pub fn wave(&self, wave_number: u32) -> WaveSpec {
+		let index = wave_number.max(1) as usize - 1;
+		match self.waves.get(index) {
+			Some(spec) => spec.clone(),
+			None => self.endless.generate((index - self.waves.len()) as u32, &self.waves),
+		}
+	}
+}
+
+impl EndlessWaves {
+	// `endless_index` is 0 for the first wave after the script runs out.  `scripted` is for looking up EndlessBoss::Wave.
+	pub fn generate(&self, endless_index: u32, scripted: &[WaveSpec]) -> WaveSpec {
+		let total = self.base_count + (self.count_per_wave * endless_index as f32) as u32;
+		let interval = (self.base_interval * self.interval_decay.powi(endless_index as i32)).max(self.min_interval);
+		let patterns = if self.patterns.is_empty() { vec![SpawnPattern::Random] } else { self.patterns.clone() };
+
+		// Split the total across the patterns.  The first groups soak up the remainder.
+		let per_group = total / patterns.len() as u32;
+		let remainder = total % patterns.len() as u32;
+		let groups = patterns.into_iter().enumerate().map(|(i, pattern)| SpawnGroup {
+			archetype: None,
+			count: per_group + if (i as u32) < remainder { 1 } else { 0 },
+			interval,
+			pattern,
+			delay: i as f32 * self.group_stagger,
+			telegraph: None,
+		}).filter(|g| g.count > 0).collect();
+
+		let boss_wave = self.boss_every > 0 && (endless_index + 1) % self.boss_every == 0;
+		WaveSpec {
+			groups,
+			boss: if boss_wave { self.boss(scripted) } else { None },
+		}
+	}
+
+	fn boss(&self, scripted: &[WaveSpec]) -> Option<BossSpec> {
+		match self.boss.as_ref()? {
+			EndlessBoss::Wave(wave_number) => scripted.get((*wave_number as usize).checked_sub(1)?)?.boss.clone(),
+			EndlessBoss::Spec(spec) => Some(spec.clone()),
+		}
+	}
+}
+
+#[derive(Default)]
+pub struct WaveScriptLoader;
+
+impl AssetLoader for WaveScriptLoader {
+	fn load<'a>(
+		&'a self,
+		bytes: &'a [u8],
+		load_context: &'a mut LoadContext,
+	) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+		Box::pin(async move {
+			let script: WaveScript = ron::de::from_bytes(bytes)?;
+			// Catch a bad reference now rather than when the first endless boss wave quietly has no boss.
+			if let Some(EndlessBoss::Wave(wave_number)) = script.endless.boss {
+				if script.endless.boss(&script.waves).is_none() {
+					return Err(anyhow::anyhow!("endless.boss refers to wave {}, which has no boss", wave_number));
+				}
+			}
+			load_context.set_default_asset(LoadedAsset::new(script));
+			Ok(())
+		})
+	}
+
+	fn extensions(&self) -> &[&str] {
+		&["script.ron"]
+	}
+}
+
+// Resources:
+pub struct WaveScriptHandle(pub Handle<WaveScript>);
+
+// One thing the spawner should make this tick.  Where exactly is up to the spawner, since that depends on the player and window.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpawnRequest {
+	pub archetype: Option<String>,
+	pub pattern: SpawnPattern,
+	pub index: u32, // Which spawn of the group this is...
+	pub count: u32, // ...out of how many.
+	pub telegraph: Option<f32>,
+}
+
+#[derive(Clone, Debug)]
+struct GroupProgress {
+	group: SpawnGroup,
+	spawned: u32,
+	time_until_next: f32,
+}
+
+// Steps through one wave's groups.  Deliberately knows nothing about the ECS so it can be driven by hand.
+#[derive(Clone, Debug, Default)]
+pub struct WaveRunner {
+	groups: Vec<GroupProgress>,
+}
+
+impl WaveRunner {
+	pub fn start(&mut self, spec: WaveSpec) {
+		self.groups = spec.groups.into_iter().map(|group| GroupProgress {
+			time_until_next: group.delay,
+			spawned: 0,
+			group,
+		}).collect();
+	}
+
+	// Enemies in this wave that haven't been asked for yet.
+	pub fn pending(&self) -> u32 {
+		self.groups.iter().map(|g| g.group.count - g.spawned).sum()
+	}
+
+	pub fn tick(&mut self, dt: f32) -> Vec<SpawnRequest> {
+		let mut requests = Vec::new();
+		for progress in self.groups.iter_mut() {
+			if progress.spawned >= progress.group.count {
+				continue;
+			}
+			progress.time_until_next -= dt;
+			// Loop in case a long frame covers several intervals.
+			while progress.time_until_next <= 0.0 && progress.spawned < progress.group.count {
+				let burst = matches!(progress.group.pattern, SpawnPattern::Burst { .. });
+				let to_spawn = if burst { progress.group.count - progress.spawned } else { 1 };
+				for _ in 0..to_spawn {
+					requests.push(SpawnRequest {
+						archetype: progress.group.archetype.clone(),
+						pattern: progress.group.pattern.clone(),
+						index: progress.spawned,
+						count: progress.group.count,
+						telegraph: progress.group.telegraph,
+					});
+					progress.spawned += 1;
+				}
+				progress.time_until_next += progress.group.interval.max(0.0);
+				if progress.group.interval <= 0.0 {
+					// Zero interval means 'all at once'.  Don't spin.
+					progress.time_until_next = 0.0;
+				}
+			}
+		}
+		requests
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const DT: f32 = 0.25;
+
+	fn group(count: u32, interval: f32, pattern: SpawnPattern, delay: f32) -> SpawnGroup {
+		SpawnGroup {
+			archetype: None,
+			count,
+			interval,
+			pattern,
+			delay,
+			telegraph: None,
+		}
+	}
+
+	fn runner(groups: Vec<SpawnGroup>) -> WaveRunner {
+		let mut runner = WaveRunner::default();
+		runner.start(WaveSpec { groups, boss: None });
+		runner
+	}
+
+	#[test]
+	fn interval_spawns_one_at_a_time() {
+		let mut runner = runner(vec![group(3, 0.5, SpawnPattern::Random, 0.0)]);
+		assert_eq!(runner.pending(), 3);
+		let counts: Vec<(usize, u32)> = (0..6).map(|_| {
+			let spawned = runner.tick(DT).len();
+			(spawned, runner.pending())
+		}).collect();
+		// Due at 0, 0.5 and 1.0.  The first is only seen on the first tick, but the schedule doesn't slip because of it.
+		assert_eq!(counts, vec![(1, 2), (1, 1), (0, 1), (1, 0), (0, 0), (0, 0)]);
+	}
+
+	#[test]
+	fn long_frame_catches_up() {
+		let mut runner = runner(vec![group(4, 0.5, SpawnPattern::Random, 0.0)]);
+		let requests = runner.tick(1.1);
+		assert_eq!(requests.iter().map(|r| r.index).collect::<Vec<_>>(), vec![0, 1, 2]);
+		assert_eq!(runner.pending(), 1);
+	}
+
+	#[test]
+	fn zero_interval_spawns_everything_at_once() {
+		let mut runner = runner(vec![group(5, 0.0, SpawnPattern::ScreenEdge, 0.0)]);
+		let requests = runner.tick(DT);
+		assert_eq!(requests.len(), 5);
+		assert_eq!(requests.iter().map(|r| r.index).collect::<Vec<_>>(), vec![0, 1, 2, 3, 4]);
+		assert!(requests.iter().all(|r| r.count == 5 && r.pattern == SpawnPattern::ScreenEdge));
+		assert_eq!(runner.pending(), 0);
+		assert!(runner.tick(DT).is_empty());
+	}
+
+	#[test]
+	fn burst_ignores_interval() {
+		let pattern = SpawnPattern::Burst { radius: 50.0 };
+		let mut runner = runner(vec![group(6, 10.0, pattern.clone(), 0.0)]);
+		let requests = runner.tick(DT);
+		assert_eq!(requests.len(), 6);
+		assert!(requests.iter().all(|r| r.pattern == pattern));
+		assert_eq!(runner.pending(), 0);
+		assert!(runner.tick(20.0).is_empty());
+	}
+
+	#[test]
+	fn delay_holds_a_group_back() {
+		let mut runner = runner(vec![
+			group(2, 1.0, SpawnPattern::Random, 0.0),
+			group(3, 0.0, SpawnPattern::Burst { radius: 40.0 }, 0.6),
+		]);
+		assert_eq!(runner.pending(), 5);
+		// 0.25: the first group starts right away.  The burst is still waiting.
+		assert_eq!(runner.tick(DT).len(), 1);
+		assert_eq!(runner.pending(), 4);
+		// 0.5: nothing due.
+		assert_eq!(runner.tick(DT).len(), 0);
+		assert_eq!(runner.pending(), 4);
+		// 0.75: past the delay, so the whole burst lands.
+		let requests = runner.tick(DT);
+		assert_eq!(requests.len(), 3);
+		assert!(requests.iter().all(|r| matches!(r.pattern, SpawnPattern::Burst { .. })));
+		assert_eq!(runner.pending(), 1);
+		// 1.0: the first group's second spawn.
+		let requests = runner.tick(DT);
+		assert_eq!(requests.len(), 1);
+		assert_eq!(requests[0].pattern, SpawnPattern::Random);
+		assert_eq!(runner.pending(), 0);
+	}
+
+	fn endless(boss_every: u32) -> EndlessWaves {
+		EndlessWaves {
+			base_count: 5,
+			count_per_wave: 1.5,
+			base_interval: 1.0,
+			interval_decay: 0.5,
+			min_interval: 0.3,
+			patterns: vec![SpawnPattern::Random, SpawnPattern::ScreenEdge],
+			group_stagger: 2.0,
+			boss_every,
+			boss: Some(EndlessBoss::Spec(test_boss("Test Boss"))),
+		}
+	}
+
+	fn test_boss(name: &str) -> BossSpec {
+		BossSpec {
+			name: name.to_string(),
+			archetype: "lich".to_string(),
+			health: None,
+			phases: Vec::new(),
+		}
+	}
+
+	#[test]
+	fn script_falls_back_to_endless() {
+		let script = WaveScript {
+			waves: vec![WaveSpec { groups: vec![group(1, 0.0, SpawnPattern::Random, 0.0)], boss: None }],
+			endless: endless(2),
+		};
+		// Wave 0 is treated as wave 1, which is the scripted one.
+		assert_eq!(script.wave(0).groups.len(), 1);
+		assert_eq!(script.wave(1).groups[0].count, 1);
+
+		// Wave 2 is the first endless wave: 5 enemies split over two patterns, the first one taking the odd one.
+		let first = script.wave(2);
+		assert_eq!(first.groups.iter().map(|g| g.count).collect::<Vec<_>>(), vec![3, 2]);
+		assert_eq!(first.groups.iter().map(|g| g.delay).collect::<Vec<_>>(), vec![0.0, 2.0]);
+		assert_eq!(first.groups[0].interval, 1.0);
+		assert!(first.boss.is_none());
+
+		// Wave 3: 6 enemies, half the interval, and every second endless wave has the boss.
+		let second = script.wave(3);
+		assert_eq!(second.groups.iter().map(|g| g.count).collect::<Vec<_>>(), vec![3, 3]);
+		assert_eq!(second.groups[0].interval, 0.5);
+		assert_eq!(second.boss.as_ref().map(|b| b.name.as_str()), Some("Test Boss"));
+
+		// Far enough in, the interval bottoms out.
+		assert_eq!(script.wave(10).groups[0].interval, 0.3);
+
+		// And the runner takes it like any other wave.
+		let mut runner = WaveRunner::default();
+		runner.start(first);
+		assert_eq!(runner.pending(), 5);
+		assert_eq!(runner.tick(DT).len(), 1);
+		assert_eq!(runner.pending(), 4);
+	}
+
+	#[test]
+	fn endless_boss_can_reuse_a_scripted_one() {
+		let mut script = WaveScript {
+			waves: vec![
+				WaveSpec { groups: Vec::new(), boss: None },
+				WaveSpec { groups: Vec::new(), boss: Some(test_boss("Scripted Boss")) },
+			],
+			endless: endless(1),
+		};
+		script.endless.boss = Some(EndlessBoss::Wave(2));
+		assert_eq!(script.wave(3).boss.map(|b| b.name), Some("Scripted Boss".to_string()));
+		script.endless.boss = Some(EndlessBoss::Wave(1));
+		assert!(script.wave(3).boss.is_none());
+		script.endless.boss = Some(EndlessBoss::Wave(0));
+		assert!(script.wave(3).boss.is_none());
+	}
+}